@@ -0,0 +1,201 @@
+/*!
+Time the audio thread while other threads flood it with commands.
+
+Controller threads move every source, and a composer thread adds sources and moves the listener,
+as fast as they can. The audio thread renders the scene in blocks, like an audio callback, and
+the benchmark reports how long the blocks take with and without this load, and how many blocks
+miss the real-time budget. Because the command queues never lock on the audio thread, the loaded
+blocks should take about as long as the idle ones; the remaining difference comes from the
+controller threads competing for the CPU. The benchmark fails if more than one percent of the
+blocks miss the budget under load but not when idle.
+
+The short sources that the composer adds take the master bus past the number of sources for
+which it initially reserves room. The benchmark also counts the allocations and deallocations on
+the audio thread while it renders, and fails if there are any.
+
+Run with `cargo bench --bench command_queue`.
+*/
+use ambisonic::sources::Constant;
+use ambisonic::{bmixer, BmixerComposer, BstreamConfig, BstreamMixer, Orientation};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: u32 = 48000;
+const BLOCK_SIZE: usize = 512;
+const BLOCKS: usize = 1000;
+const SOURCES: usize = 64;
+const CONTROLLER_THREADS: usize = 4;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Number of allocations and deallocations while rendering
+static RENDER_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static RENDERING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Counts the allocations and deallocations of the thread that renders the scene
+struct CountingAllocator;
+
+impl CountingAllocator {
+    fn count(&self) {
+        if RENDERING.try_with(Cell::get).unwrap_or(false) {
+            RENDER_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.count();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.count();
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.count();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+fn main() {
+    let idle = render_scene(false);
+    let loaded = render_scene(true);
+
+    let budget = Duration::from_secs_f64(BLOCK_SIZE as f64 / SAMPLE_RATE as f64);
+    println!(
+        "{} sources, {} blocks of {} frames, real-time budget {:?} per block",
+        SOURCES, BLOCKS, BLOCK_SIZE, budget
+    );
+    let idle_late = report("idle", &idle, budget);
+    let loaded_late = report("loaded", &loaded, budget);
+
+    let allocations = RENDER_ALLOCATIONS.load(Ordering::Relaxed);
+    println!("{} allocations while rendering", allocations);
+
+    assert!(
+        loaded_late <= idle_late + BLOCKS / 100,
+        "{} blocks missed the budget under load, {} when idle",
+        loaded_late,
+        idle_late
+    );
+    assert_eq!(allocations, 0, "the audio thread allocated");
+}
+
+/// Render the scene and return the duration of each block
+fn render_scene(loaded: bool) -> Vec<Duration> {
+    let (mut mixer, composer) = bmixer(SAMPLE_RATE);
+
+    let mut controllers: Vec<_> = (0..SOURCES)
+        .map(|i| {
+            let config = BstreamConfig::new()
+                .with_position(position(i, 0))
+                .with_world_space(i % 2 == 0);
+            composer.play(Constant::new(0.01, SAMPLE_RATE), config)
+        })
+        .collect();
+
+    // let the mixer pick up the sources before timing
+    render_block(&mut mixer);
+
+    let running = Arc::new(AtomicBool::new(true));
+    let mut threads = Vec::new();
+    if loaded {
+        let chunk_size = SOURCES / CONTROLLER_THREADS;
+        while !controllers.is_empty() {
+            let mut chunk: Vec<_> = controllers
+                .drain(..chunk_size.min(controllers.len()))
+                .collect();
+            let running = running.clone();
+            threads.push(thread::spawn(move || {
+                let mut step = 0;
+                while running.load(Ordering::Relaxed) {
+                    step += 1;
+                    for (i, controller) in chunk.iter_mut().enumerate() {
+                        controller.adjust_position(position(i, step));
+                        controller.set_velocity([0.0, (step % 7) as f32, 0.0]);
+                    }
+                    thread::yield_now();
+                }
+            }));
+        }
+
+        let running = running.clone();
+        let composer = composer.clone();
+        threads.push(thread::spawn(move || compose(&composer, &running)));
+    }
+
+    let times = (0..BLOCKS)
+        .map(|_| {
+            let start = Instant::now();
+            render_block(&mut mixer);
+            start.elapsed()
+        })
+        .collect();
+
+    running.store(false, Ordering::Relaxed);
+    threads.into_iter().for_each(|t| t.join().unwrap());
+    times
+}
+
+/// Add short sources and move the listener until stopped
+fn compose(composer: &Arc<BmixerComposer>, running: &AtomicBool) {
+    let listener = composer.listener();
+    let mut step = 0;
+    while running.load(Ordering::Relaxed) {
+        step += 1;
+        let angle = step as f32 * 0.01;
+        listener.set_position([angle.sin(), 0.0, 0.0]);
+        composer.set_listener_orientation(Orientation::from_yaw_pitch_roll(angle, 0.0, 0.0));
+        if step % 100 == 0 {
+            let short = Constant::new(0.01, SAMPLE_RATE);
+            let short = rodio::Source::take_duration(short, Duration::from_millis(10));
+            composer.play(short, BstreamConfig::new().with_position(position(0, step)));
+        }
+        thread::yield_now();
+    }
+}
+
+fn render_block(mixer: &mut BstreamMixer) {
+    RENDERING.with(|rendering| rendering.set(true));
+    for _ in 0..BLOCK_SIZE {
+        std::hint::black_box(mixer.next_frame());
+    }
+    RENDERING.with(|rendering| rendering.set(false));
+}
+
+/// Position of a source on a slowly turning circle around the listener
+fn position(index: usize, step: usize) -> [f32; 3] {
+    let angle = index as f32 + step as f32 * 0.001;
+    [5.0 * angle.cos(), 5.0 * angle.sin(), 0.0]
+}
+
+/// Print statistics of the block durations and return the number of blocks over budget
+fn report(name: &str, times: &[Duration], budget: Duration) -> usize {
+    let mut sorted = times.to_vec();
+    sorted.sort();
+    let mean = times.iter().sum::<Duration>() / times.len() as u32;
+    let p99 = sorted[sorted.len() * 99 / 100];
+    let max = sorted[sorted.len() - 1];
+    let late = times.iter().filter(|&&t| t > budget).count();
+    println!(
+        "{:>8}: mean {:>10.3?}  p99 {:>10.3?}  max {:>10.3?}  ({:.0}% of budget)  {} late",
+        name,
+        mean,
+        p99,
+        max,
+        100.0 * max.as_secs_f64() / budget.as_secs_f64(),
+        late
+    );
+    late
+}
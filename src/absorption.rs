@@ -0,0 +1,241 @@
+//! Absorption of high frequencies by the air
+
+use std::f32;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Model for the low-pass filtering of sound sources by the air between source and listener.
+///
+/// Air absorbs high frequencies much more than low frequencies, which makes distant sounds
+/// dull. The model maps the distance of a source to the cutoff frequency of a low-pass filter.
+///
+/// The physical model computes the attenuation of air according to ISO 9613-1 and places the
+/// cutoff at the frequency that is attenuated by 3 dB over the source's distance. Distances are
+/// in metres. By default, sources are not filtered.
+#[derive(Clone)]
+pub struct AirAbsorption {
+    kind: Kind,
+}
+
+#[derive(Clone)]
+enum Kind {
+    None,
+    Atmosphere(Atmosphere),
+    Custom(Arc<dyn Fn(f32) -> f32 + Send + Sync>),
+}
+
+impl AirAbsorption {
+    /// Sources are not filtered.
+    pub fn none() -> Self {
+        AirAbsorption { kind: Kind::None }
+    }
+
+    /// Absorption of air at 20 ºC and 50 % relative humidity.
+    pub fn standard() -> Self {
+        Self::from_conditions(20.0, 50.0)
+    }
+
+    /// Absorption of air at given temperature (in ºC) and relative humidity (in %), at sea
+    /// level.
+    pub fn from_conditions(temperature: f32, humidity: f32) -> Self {
+        AirAbsorption {
+            kind: Kind::Atmosphere(Atmosphere::new(temperature, humidity)),
+        }
+    }
+
+    /// User-supplied curve that maps distance to cutoff frequency in Hz.
+    pub fn custom<F>(curve: F) -> Self
+    where
+        F: Fn(f32) -> f32 + Send + Sync + 'static,
+    {
+        AirAbsorption {
+            kind: Kind::Custom(Arc::new(curve)),
+        }
+    }
+
+    /// Attenuation of air in dB per metre at given frequency.
+    ///
+    /// Returns zero for models without physical parameters.
+    pub fn attenuation(&self, frequency: f32) -> f32 {
+        match &self.kind {
+            Kind::Atmosphere(atmosphere) => atmosphere.attenuation(frequency),
+            Kind::None | Kind::Custom(_) => 0.0,
+        }
+    }
+
+    /// Cutoff frequency of a source at given distance, or `None` if it is not filtered.
+    pub fn cutoff(&self, distance: f32) -> Option<f32> {
+        match &self.kind {
+            Kind::None => None,
+            Kind::Custom(curve) => Some(curve(distance)).filter(|f| f.is_finite()),
+            Kind::Atmosphere(atmosphere) => {
+                if atmosphere.attenuation(MAX_CUTOFF) * distance <= CUTOFF_ATTENUATION {
+                    return None;
+                }
+
+                // bisection in the logarithmic domain; the attenuation increases with frequency
+                let (mut lo, mut hi) = (MIN_CUTOFF.ln(), MAX_CUTOFF.ln());
+                for _ in 0..24 {
+                    let mid = (lo + hi) / 2.0;
+                    if atmosphere.attenuation(mid.exp()) * distance > CUTOFF_ATTENUATION {
+                        hi = mid;
+                    } else {
+                        lo = mid;
+                    }
+                }
+                Some(lo.exp())
+            }
+        }
+    }
+}
+
+impl Default for AirAbsorption {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+/// Attenuation at the cutoff frequency, in dB
+const CUTOFF_ATTENUATION: f32 = 3.0;
+
+const MIN_CUTOFF: f32 = 20.0;
+const MAX_CUTOFF: f32 = 20000.0;
+
+/// Frequency-independent parameters of the ISO 9613-1 absorption model
+#[derive(Clone)]
+struct Atmosphere {
+    relative_temperature: f32,
+    oxygen_relaxation: f32,
+    nitrogen_relaxation: f32,
+    oxygen_factor: f32,
+    nitrogen_factor: f32,
+}
+
+impl Atmosphere {
+    fn new(temperature: f32, humidity: f32) -> Self {
+        let t = temperature + 273.15;
+        let tr = t / 293.15;
+
+        // molar concentration of water vapour in %
+        let c = -6.8346 * (273.16 / t).powf(1.261) + 4.6151;
+        let h = humidity * 10f32.powf(c);
+
+        Atmosphere {
+            relative_temperature: tr,
+            oxygen_relaxation: 24.0 + 4.04e4 * h * (0.02 + h) / (0.391 + h),
+            nitrogen_relaxation: tr.powf(-0.5)
+                * (9.0 + 280.0 * h * (-4.170 * (tr.powf(-1.0 / 3.0) - 1.0)).exp()),
+            oxygen_factor: 0.01275 * (-2239.1 / t).exp(),
+            nitrogen_factor: 0.1068 * (-3352.0 / t).exp(),
+        }
+    }
+
+    /// Attenuation in dB per metre
+    fn attenuation(&self, f: f32) -> f32 {
+        let ff = f * f;
+        let tr = self.relative_temperature;
+        let fr_o = self.oxygen_relaxation;
+        let fr_n = self.nitrogen_relaxation;
+
+        8.686
+            * ff
+            * (1.84e-11 * tr.sqrt()
+                + tr.powf(-2.5)
+                    * (self.oxygen_factor / (fr_o + ff / fr_o)
+                        + self.nitrogen_factor / (fr_n + ff / fr_n)))
+    }
+}
+
+/// Smoothly adjustable second-order low-pass filter (two cascaded one-pole filters)
+///
+/// The cascade attenuates the cutoff frequency by 3 dB.
+pub(crate) struct Lowpass {
+    sample_rate: f32,
+    coefficient: f32,
+    target: f32,
+    state: [f32; 2],
+}
+
+impl Lowpass {
+    pub(crate) fn new(cutoff: Option<f32>, sample_rate: u32) -> Self {
+        let mut filter = Lowpass {
+            sample_rate: sample_rate as f32,
+            coefficient: 0.0,
+            target: 0.0,
+            state: [0.0; 2],
+        };
+        filter.set_cutoff(cutoff);
+        filter.coefficient = filter.target;
+        filter
+    }
+
+    /// Move the cutoff frequency towards a new value, or remove the filter if `None`
+    pub(crate) fn set_cutoff(&mut self, cutoff: Option<f32>) {
+        self.target = match cutoff {
+            Some(f) => (-2.0 * PI * f.max(1.0) * POLE_FACTOR / self.sample_rate).exp(),
+            None => 0.0,
+        };
+    }
+
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        self.coefficient += (self.target - self.coefficient) * SMOOTHING;
+        let a = self.coefficient;
+        self.state[0] = x * (1.0 - a) + self.state[0] * a;
+        self.state[1] = self.state[0] * (1.0 - a) + self.state[1] * a;
+        self.state[1]
+    }
+}
+
+/// Fraction of the remaining change of the filter coefficient applied per sample
+const SMOOTHING: f32 = 0.002;
+
+/// Ratio of the pole frequency of each one-pole filter to the cutoff of the cascade,
+/// `1 / sqrt(sqrt(2) - 1)`
+const POLE_FACTOR: f32 = 1.553_774;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_atmosphere_matches_iso_tables() {
+        // ISO 9613-1 lists 4.66 dB/km at 1 kHz and 9.86 dB/km at 2 kHz for 20 ºC and 50 %
+        let air = AirAbsorption::standard();
+        assert!((air.attenuation(1000.0) * 1000.0 - 4.66).abs() < 0.05);
+        assert!((air.attenuation(2000.0) * 1000.0 - 9.86).abs() < 0.05);
+    }
+
+    #[test]
+    fn cutoff_decreases_with_distance() {
+        let air = AirAbsorption::standard();
+        assert_eq!(air.cutoff(1.0), None);
+
+        let near = air.cutoff(100.0).unwrap();
+        let far = air.cutoff(1000.0).unwrap();
+        assert!(far < near);
+        assert!((air.attenuation(far) * 1000.0 - CUTOFF_ATTENUATION).abs() < 0.01);
+    }
+
+    #[test]
+    fn lowpass_attenuates_high_frequencies() {
+        let mut filter = Lowpass::new(Some(100.0), 48000);
+        let output: Vec<f32> = (0..1000)
+            .map(|i| filter.process(if i % 2 == 0 { 1.0 } else { -1.0 }))
+            .collect();
+        assert!(output[500..].iter().all(|x| x.abs() < 0.01));
+
+        let mut bypass = Lowpass::new(None, 48000);
+        assert_eq!(bypass.process(1.0), 1.0);
+        assert_eq!(bypass.process(-1.0), -1.0);
+    }
+
+    #[test]
+    fn lowpass_attenuates_cutoff_by_three_decibels() {
+        let mut filter = Lowpass::new(Some(1000.0), 48000);
+        let peak = (0..48000)
+            .map(|i| filter.process((2.0 * PI * 1000.0 * i as f32 / 48000.0).sin()))
+            .skip(24000)
+            .fold(0.0, f32::max);
+        assert!((20.0 * peak.log10() + 3.0).abs() < 0.2, "{}", peak);
+    }
+}
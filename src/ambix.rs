@@ -0,0 +1,300 @@
+//! Read and write *B-format* WAV files in AmbiX format
+//!
+//! AmbiX files store the components in ACN order with SN3D normalization, in a coordinate system
+//! with `x` to the front, `y` to the left, and `z` up. Components are converted from and to the
+//! Furse-Malham representation used by this crate while reading and writing.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use rodio::Source;
+
+use crate::bformat::{fuma_to_sn3d, AmbisonicOrder, Bformat, MAX_CHANNELS};
+use crate::error::Error;
+
+/// ACN channel of each Furse-Malham component
+const ACN_CHANNELS: [usize; MAX_CHANNELS] = [0, 1, 3, 2, 6, 5, 7, 8, 4, 12, 11, 13, 14, 10, 9, 15];
+
+/// Sign change of each Furse-Malham component, caused by the rotated coordinate system
+const ACN_SIGNS: [f32; MAX_CHANNELS] = [
+    1.0, -1.0, 1.0, 1.0, 1.0, -1.0, 1.0, -1.0, -1.0, 1.0, -1.0, 1.0, -1.0, -1.0, 1.0, -1.0,
+];
+
+/// Convert AmbiX channels to a *B-format* sample
+fn from_ambix(channels: &[f32]) -> Bformat {
+    let mut components = [0.0; MAX_CHANNELS];
+    for (c, x) in components.iter_mut().enumerate() {
+        if let Some(a) = channels.get(ACN_CHANNELS[c]) {
+            *x = a * ACN_SIGNS[c] / fuma_to_sn3d(c);
+        }
+    }
+    Bformat::from_components(components)
+}
+
+/// Convert a *B-format* sample to AmbiX channels
+fn to_ambix(sample: &Bformat, channels: &mut [f32]) {
+    for (c, x) in sample.components().iter().enumerate() {
+        if let Some(a) = channels.get_mut(ACN_CHANNELS[c]) {
+            *a = x * ACN_SIGNS[c] * fuma_to_sn3d(c);
+        }
+    }
+}
+
+/// Play an AmbiX WAV file as a *B-format* source.
+///
+/// The file must contain 4, 9, or 16 channels for first, second, or third order. Integer and
+/// floating point samples are supported. Playback ends early if the file turns out to be
+/// corrupt while reading.
+///
+/// The reader produces one interleaved channel per component, converted to the Furse-Malham
+/// representation of this crate. Wrap it in `BformatFrames` to read whole *B-format* frames.
+///
+/// Use `Ambisonic::play_bformat` to add the recording to a sound scene.
+pub struct AmbixReader {
+    samples: Box<dyn Iterator<Item = Result<f32, hound::Error>> + Send>,
+    order: AmbisonicOrder,
+    sample_rate: u32,
+    duration: Duration,
+    frame: Bformat,
+    ambix_frame: Vec<f32>,
+    position: usize,
+}
+
+impl AmbixReader {
+    /// Open an AmbiX file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Read AmbiX data from a reader
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> Result<Self, Error> {
+        let reader = WavReader::new(reader)?;
+        let spec = reader.spec();
+
+        let order = AmbisonicOrder::from_channels(spec.channels as usize).ok_or(
+            hound::Error::FormatError("AmbiX files must have 4, 9, or 16 channels"),
+        )?;
+        let duration = Duration::from_secs_f64(reader.duration() as f64 / spec.sample_rate as f64);
+
+        let samples: Box<dyn Iterator<Item = Result<f32, hound::Error>> + Send> =
+            match spec.sample_format {
+                SampleFormat::Float => Box::new(reader.into_samples::<f32>()),
+                SampleFormat::Int => {
+                    let scale = 1.0 / (1u32 << (spec.bits_per_sample - 1)) as f32;
+                    Box::new(
+                        reader
+                            .into_samples::<i32>()
+                            .map(move |s| s.map(|s| s as f32 * scale)),
+                    )
+                }
+            };
+
+        Ok(AmbixReader {
+            samples,
+            order,
+            sample_rate: spec.sample_rate,
+            duration,
+            frame: Bformat::zero(),
+            ambix_frame: vec![0.0; order.channels()],
+            position: order.channels(),
+        })
+    }
+
+    /// Ambisonic order of the recording
+    pub fn order(&self) -> AmbisonicOrder {
+        self.order
+    }
+}
+
+impl Source for AmbixReader {
+    #[inline(always)]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline(always)]
+    fn channels(&self) -> u16 {
+        self.order.channels() as u16
+    }
+
+    #[inline(always)]
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[inline(always)]
+    fn total_duration(&self) -> Option<Duration> {
+        Some(self.duration)
+    }
+}
+
+impl Iterator for AmbixReader {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.ambix_frame.len() {
+            for x in &mut self.ambix_frame {
+                *x = self.samples.next()?.ok()?;
+            }
+            self.frame = from_ambix(&self.ambix_frame);
+            self.position = 0;
+        }
+        let x = self.frame.components()[self.position];
+        self.position += 1;
+        Some(x)
+    }
+}
+
+/// Write *B-format* samples to an AmbiX WAV file.
+///
+/// Samples are stored as 32 bit floating point values. To export a sound scene, pass frames
+/// of a `BstreamMixer` to `write`:
+///
+/// ```no_run
+/// use ambisonic::{bmixer_with_order, AmbisonicOrder, AmbixWriter, BstreamConfig};
+///
+/// let (mut mixer, composer) = bmixer_with_order(48000, AmbisonicOrder::Third);
+/// composer.play(
+///     ambisonic::rodio::source::SineWave::new(440.0),
+///     BstreamConfig::new().with_position([1.0, 1.0, 0.0]),
+/// );
+///
+/// let mut writer = AmbixWriter::create("scene.wav", 48000, AmbisonicOrder::Third).unwrap();
+/// writer.write((0..48000 * 10).map(|_| mixer.next_frame())).unwrap();
+/// writer.finalize().unwrap();
+/// ```
+pub struct AmbixWriter<W: Write + Seek> {
+    writer: WavWriter<W>,
+    frame: Vec<f32>,
+}
+
+impl AmbixWriter<BufWriter<File>> {
+    /// Create an AmbiX file of given sample rate and ambisonic order
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        order: AmbisonicOrder,
+    ) -> Result<Self, Error> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate, order)
+    }
+}
+
+impl<W: Write + Seek> AmbixWriter<W> {
+    /// Write AmbiX data of given sample rate and ambisonic order to a writer
+    pub fn new(writer: W, sample_rate: u32, order: AmbisonicOrder) -> Result<Self, Error> {
+        let spec = WavSpec {
+            channels: order.channels() as u16,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        Ok(AmbixWriter {
+            writer: WavWriter::new(writer, spec)?,
+            frame: vec![0.0; order.channels()],
+        })
+    }
+
+    /// Write a single sample
+    ///
+    /// Components above the order of the file are discarded.
+    pub fn write_sample(&mut self, sample: Bformat) -> Result<(), Error> {
+        to_ambix(&sample, &mut self.frame);
+        for x in &self.frame {
+            self.writer.write_sample(*x)?;
+        }
+        Ok(())
+    }
+
+    /// Write all samples of an iterator
+    pub fn write<I: IntoIterator<Item = Bformat>>(&mut self, samples: I) -> Result<(), Error> {
+        for sample in samples {
+            self.write_sample(sample)?;
+        }
+        Ok(())
+    }
+
+    /// Update the file header and flush all data
+    ///
+    /// The file is also finalized when the writer is dropped, but errors are ignored then.
+    pub fn finalize(self) -> Result<(), Error> {
+        Ok(self.writer.finalize()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bformat::{BformatFrames, Bweights};
+    use std::io::Cursor;
+
+    #[test]
+    fn components_follow_ambix_conventions() {
+        // source front-left and above, in the crate's coordinates
+        let (x, y, z) = (-0.36f32, 0.48, 0.8);
+        let mut channels = [0.0; MAX_CHANNELS];
+        to_ambix(
+            &Bweights::from_direction([x, y, z]).scale(1.0),
+            &mut channels,
+        );
+
+        // AmbiX coordinates: x to the front, y to the left
+        let (x, y) = (y, -x);
+        let expected = [
+            (0, 1.0),
+            (1, y),
+            (2, z),
+            (3, x),
+            (4, 3f32.sqrt() * x * y),
+            (9, (5.0f32 / 8.0).sqrt() * y * (3.0 * x * x - y * y)),
+            (10, 15f32.sqrt() * x * y * z),
+            (15, (5.0f32 / 8.0).sqrt() * x * (x * x - 3.0 * y * y)),
+        ];
+        for &(acn, value) in &expected {
+            assert!((channels[acn] - value).abs() < 1e-5, "ACN {}", acn);
+        }
+    }
+
+    #[test]
+    fn written_files_can_be_read() {
+        let samples: Vec<Bformat> = (0..10)
+            .map(|i| Bweights::from_direction([1.0, i as f32, 0.5]).scale(0.5))
+            .collect();
+
+        let mut data = Cursor::new(Vec::new());
+        let mut writer = AmbixWriter::new(&mut data, 44100, AmbisonicOrder::Third).unwrap();
+        writer.write(samples.iter().cloned()).unwrap();
+        writer.finalize().unwrap();
+
+        let reader = AmbixReader::from_reader(Cursor::new(data.into_inner())).unwrap();
+        assert_eq!(reader.order(), AmbisonicOrder::Third);
+        assert_eq!(reader.sample_rate(), 44100);
+
+        assert_eq!(reader.channels(), 16);
+
+        let read: Vec<Bformat> = BformatFrames::new(reader).collect();
+        assert_eq!(read.len(), samples.len());
+        for (a, b) in read.iter().zip(&samples) {
+            for (x, y) in a.components().iter().zip(b.components()) {
+                assert!((x - y).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_channel_counts_are_rejected() {
+        let mut data = Cursor::new(Vec::new());
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        WavWriter::new(&mut data, spec).unwrap().finalize().unwrap();
+
+        let result = AmbixReader::from_reader(Cursor::new(data.into_inner()));
+        assert!(matches!(result, Err(Error::Wav(_))));
+    }
+}
@@ -1,122 +1,469 @@
 //! *B-format* representation of audio samples
 
 use std::iter::FromIterator;
+use std::ops::{Add, AddAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use std::time::Duration;
 
-use cpal::{Sample as CpalSample, SampleFormat};
-use rodio::Sample;
+use rodio::Source;
 
-/// Audio sample in first-order *B-format*.
+use crate::distance::DistanceModel;
+
+/// Highest ambisonic order supported by the *B-format* types.
+pub const MAX_ORDER: usize = 3;
+
+/// Number of components in a *B-format* sample of `MAX_ORDER`.
+pub const MAX_CHANNELS: usize = (MAX_ORDER + 1) * (MAX_ORDER + 1);
+
+/// Ambisonic order of a *B-format* representation.
 ///
-/// It encodes four components of the sound field at the listener position: omnidirectional level
-/// `w` and the level gradient in `x`, `y`, and `z` directions.
-#[derive(Debug, Copy, Clone)]
+/// Higher orders capture more spatial detail, which results in sharper localisation of sound
+/// sources, at the cost of more components per sample.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AmbisonicOrder {
+    /// First order: four components (`w`, `x`, `y`, `z`)
+    #[default]
+    First,
+    /// Second order: nine components
+    Second,
+    /// Third order: sixteen components
+    Third,
+}
+
+impl AmbisonicOrder {
+    /// Construct the order from its numeric value.
+    ///
+    /// Returns `None` if the order is not supported.
+    pub fn from_degree(degree: usize) -> Option<Self> {
+        match degree {
+            1 => Some(AmbisonicOrder::First),
+            2 => Some(AmbisonicOrder::Second),
+            3 => Some(AmbisonicOrder::Third),
+            _ => None,
+        }
+    }
+
+    /// Construct the order that corresponds to a given number of components.
+    ///
+    /// Returns `None` if `n` is not the number of components of a supported order.
+    pub fn from_channels(n: usize) -> Option<Self> {
+        match n {
+            4 => Some(AmbisonicOrder::First),
+            9 => Some(AmbisonicOrder::Second),
+            16 => Some(AmbisonicOrder::Third),
+            _ => None,
+        }
+    }
+
+    /// Numeric value of the order.
+    pub fn degree(self) -> usize {
+        match self {
+            AmbisonicOrder::First => 1,
+            AmbisonicOrder::Second => 2,
+            AmbisonicOrder::Third => 3,
+        }
+    }
+
+    /// Number of *B-format* components of this order.
+    pub fn channels(self) -> usize {
+        (self.degree() + 1) * (self.degree() + 1)
+    }
+}
+
+/// Degree of each *B-format* component, in Furse-Malham order.
+pub(crate) const COMPONENT_DEGREES: [usize; MAX_CHANNELS] =
+    [0, 1, 1, 1, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3];
+
+/// Factor that converts a Furse-Malham normalized component to SN3D normalization.
+pub(crate) fn fuma_to_sn3d(component: usize) -> f32 {
+    match component {
+        0 => 2f32.sqrt(),
+        5..=8 => 3f32.sqrt() / 2.0,
+        10 | 11 => (32.0f32 / 45.0).sqrt(),
+        12 | 13 => 5f32.sqrt() / 3.0,
+        14 | 15 => (5.0f32 / 8.0).sqrt(),
+        _ => 1.0,
+    }
+}
+
+/// Length of a vector
+pub(crate) fn norm(v: [f32; 3]) -> f32 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+/// Audio sample in *B-format*.
+///
+/// It encodes the components of the sound field at the listener position up to third order.
+/// The first four components are the omnidirectional level `w` and the level gradient in `x`,
+/// `y`, and `z` directions. Components beyond the order of a scene are zero.
+///
+/// Components are stored in Furse-Malham order (`W X Y Z R S T U V K L M N O P Q`) and
+/// normalization, with azimuth measured from the `x` axis towards the `y` axis (i.e. the
+/// listener faces `y` and `x` is to the right).
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct Bformat {
-    w: f32,
-    x: f32,
-    y: f32,
-    z: f32,
+    components: [f32; MAX_CHANNELS],
 }
 
-impl Sample for Bformat {
-    fn lerp(first: Self, second: Self, numerator: u32, denominator: u32) -> Self {
-        let alpha = numerator as f32 / denominator as f32;
-        Bformat {
-            w: first.w * alpha + second.w * (1.0 - alpha),
-            x: first.x * alpha + second.x * (1.0 - alpha),
-            y: first.y * alpha + second.y * (1.0 - alpha),
-            z: first.z * alpha + second.z * (1.0 - alpha),
+impl Bformat {
+    /// Silence: a sample with all components zero.
+    pub fn zero() -> Self {
+        Bformat::default()
+    }
+
+    /// Construct a sample from its components.
+    pub fn from_components(components: [f32; MAX_CHANNELS]) -> Self {
+        Bformat { components }
+    }
+
+    /// Access the components of the sample.
+    pub fn components(&self) -> &[f32; MAX_CHANNELS] {
+        &self.components
+    }
+
+    /// Mutably access the components of the sample.
+    pub fn components_mut(&mut self) -> &mut [f32; MAX_CHANNELS] {
+        &mut self.components
+    }
+
+    /// Omnidirectional component
+    pub fn w(&self) -> f32 {
+        self.components[0]
+    }
+
+    /// First-order component in `x` direction (right)
+    pub fn x(&self) -> f32 {
+        self.components[1]
+    }
+
+    /// First-order component in `y` direction (front)
+    pub fn y(&self) -> f32 {
+        self.components[2]
+    }
+
+    /// First-order component in `z` direction (up)
+    pub fn z(&self) -> f32 {
+        self.components[3]
+    }
+
+    /// Set all components above the given order to zero.
+    pub fn truncate(&mut self, order: AmbisonicOrder) {
+        for c in &mut self.components[order.channels()..] {
+            *c = 0.0;
         }
     }
+}
+
+impl Add for Bformat {
+    type Output = Bformat;
+
+    fn add(mut self, other: Bformat) -> Bformat {
+        self += other;
+        self
+    }
+}
 
-    fn amplify(self, alpha: f32) -> Self {
-        Bformat {
-            w: self.w * alpha,
-            x: self.x * alpha,
-            y: self.y * alpha,
-            z: self.z * alpha,
+impl AddAssign for Bformat {
+    fn add_assign(&mut self, other: Bformat) {
+        for (c, o) in self.components.iter_mut().zip(&other.components) {
+            *c += o;
         }
     }
+}
+
+impl Sub for Bformat {
+    type Output = Bformat;
+
+    fn sub(mut self, other: Bformat) -> Bformat {
+        self -= other;
+        self
+    }
+}
 
-    fn saturating_add(self, other: Self) -> Self {
-        Bformat {
-            w: self.w + other.w,
-            x: self.x + other.x,
-            y: self.y + other.y,
-            z: self.z + other.z,
+impl SubAssign for Bformat {
+    fn sub_assign(&mut self, other: Bformat) {
+        for (c, o) in self.components.iter_mut().zip(&other.components) {
+            *c -= o;
         }
     }
+}
+
+impl Mul<f32> for Bformat {
+    type Output = Bformat;
+
+    fn mul(mut self, gain: f32) -> Bformat {
+        self *= gain;
+        self
+    }
+}
 
-    fn zero_value() -> Self {
-        Bformat {
-            w: 0.0,
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
+impl MulAssign<f32> for Bformat {
+    fn mul_assign(&mut self, gain: f32) {
+        for c in &mut self.components {
+            *c *= gain;
         }
     }
 }
 
-// Why? Oh, why!?
-unsafe impl CpalSample for Bformat {
-    const FORMAT: SampleFormat = SampleFormat::F32;
+impl Neg for Bformat {
+    type Output = Bformat;
 
-    fn to_f32(&self) -> f32 {
-        panic!("The B-format is not intended to be used as a CPAL sample directly. Use a renderer instead.")
+    fn neg(self) -> Bformat {
+        self * -1.0
     }
+}
 
-    fn to_i16(&self) -> i16 {
-        panic!("The B-format is not intended to be used as a CPAL sample directly. Use a renderer instead.")
+/// Read *B-format* frames from an interleaved `rodio::Source`.
+///
+/// Each frame consists of `channels()` consecutive samples of the inner source, which are the
+/// components in Furse-Malham order. Missing components are zero, components beyond
+/// `MAX_CHANNELS` are ignored, and an incomplete frame at the end of the source is discarded.
+pub struct BformatFrames<I> {
+    input: I,
+}
+
+impl<I> BformatFrames<I> {
+    /// Read frames from an interleaved source.
+    pub fn new(input: I) -> Self {
+        BformatFrames { input }
+    }
+
+    /// Access the inner source.
+    pub fn inner(&self) -> &I {
+        &self.input
     }
 
-    fn to_u16(&self) -> u16 {
-        panic!("The B-format is not intended to be used as a CPAL sample directly. Use a renderer instead.")
+    /// Mutably access the inner source.
+    pub fn inner_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    /// Return the inner source.
+    pub fn into_inner(self) -> I {
+        self.input
+    }
+}
+
+impl<I> BformatFrames<I>
+where
+    I: Source<Item = f32>,
+{
+    /// Ambisonic order of the frames, if the number of channels matches a supported order.
+    pub fn order(&self) -> Option<AmbisonicOrder> {
+        AmbisonicOrder::from_channels(self.input.channels() as usize)
     }
 
-    fn from<S>(_s: &S) -> Self
-    where
-        S: CpalSample,
-    {
-        panic!("The B-format is not intended to be used as a CPAL sample directly. Use a renderer instead.")
+    /// Number of frames until the inner source's channel count or sample rate may change
+    pub fn current_frame_count(&self) -> Option<usize> {
+        let channels = self.input.channels().max(1) as usize;
+        self.input.current_frame_len().map(|n| n / channels)
+    }
+}
+
+impl<I> Iterator for BformatFrames<I>
+where
+    I: Source<Item = f32>,
+{
+    type Item = Bformat;
+
+    fn next(&mut self) -> Option<Bformat> {
+        let n = self.input.channels() as usize;
+        let mut frame = Bformat::zero();
+        for i in 0..n {
+            let x = self.input.next()?;
+            if let Some(c) = frame.components.get_mut(i) {
+                *c = x;
+            }
+        }
+        Some(frame)
+    }
+}
+
+/// Interleave *B-format* frames into a `rodio::Source` with one channel per component.
+///
+/// The resulting source can be used with ordinary `rodio` combinators. Components above the
+/// given order are dropped.
+pub struct BformatSource<I> {
+    frames: I,
+    order: AmbisonicOrder,
+    sample_rate: u32,
+    frame: Bformat,
+    position: usize,
+}
+
+impl<I> BformatSource<I>
+where
+    I: Iterator<Item = Bformat>,
+{
+    /// Interleave frames of given sample rate, up to the given order.
+    pub fn new(frames: I, sample_rate: u32, order: AmbisonicOrder) -> Self {
+        BformatSource {
+            frames,
+            order,
+            sample_rate,
+            frame: Bformat::zero(),
+            position: order.channels(),
+        }
+    }
+
+    /// Return the inner frame iterator.
+    pub fn into_inner(self) -> I {
+        self.frames
+    }
+}
+
+impl<I> Source for BformatSource<I>
+where
+    I: Iterator<Item = Bformat>,
+{
+    #[inline(always)]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline(always)]
+    fn channels(&self) -> u16 {
+        self.order.channels() as u16
+    }
+
+    #[inline(always)]
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[inline(always)]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<I> Iterator for BformatSource<I>
+where
+    I: Iterator<Item = Bformat>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == self.order.channels() {
+            self.frame = self.frames.next()?;
+            self.position = 0;
+        }
+        let x = self.frame.components[self.position];
+        self.position += 1;
+        Some(x)
     }
 }
 
 /// Weights for manipulating `Bformat` samples.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct Bweights {
-    w: f32,
-    x: f32,
-    y: f32,
-    z: f32,
+    components: [f32; MAX_CHANNELS],
 }
 
 impl Bweights {
-    /// Initialze new weights with given values
+    /// Initialze new first-order weights with given values
     pub fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
-        Bweights { w, x, y, z }
+        let mut components = [0.0; MAX_CHANNELS];
+        components[..4].copy_from_slice(&[w, x, y, z]);
+        Bweights { components }
+    }
+
+    /// Construct weights from their components.
+    pub fn from_components(components: [f32; MAX_CHANNELS]) -> Self {
+        Bweights { components }
     }
 
     /// Weights that correspond to a omnidirectional source
     pub fn omni_source() -> Self {
-        Bweights {
-            w: 1.0 / 2f32.sqrt(),
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        }
+        Bweights::new(1.0 / 2f32.sqrt(), 0.0, 0.0, 0.0)
     }
 
     /// Compute weights that correspond to a sound source at given position.
-    pub fn from_position(pos: [f32; 3]) -> Self {
-        let dist = (pos[0] * pos[0] + pos[1] * pos[1] + pos[2] * pos[2]).sqrt();
-        let falloff = 1.0 / dist.max(1.0); // todo: proper falloff and distance model(s)
+    ///
+    /// The source is encoded up to `MAX_ORDER` and attenuated by the given distance model.
+    pub fn from_position(pos: [f32; 3], model: &DistanceModel) -> Self {
+        let dist = norm(pos);
+        let mut weights = Bweights::from_direction(pos);
+        weights.amplify(model.gain(dist));
+        weights
+    }
+
+    /// Compute weights that encode a plane wave arriving from `direction`.
+    ///
+    /// The direction does not need to be normalized. A zero direction results in an
+    /// omnidirectional source.
+    pub fn from_direction(direction: [f32; 3]) -> Self {
+        let l = norm(direction);
+        if l == 0.0 {
+            return Bweights::omni_source();
+        }
+        let (x, y, z) = (direction[0] / l, direction[1] / l, direction[2] / l);
+
+        let (xx, yy, zz) = (x * x, y * y, z * z);
+        let c_lm = (135.0f32 / 256.0).sqrt();
+        let c_no = (27.0f32 / 4.0).sqrt();
+
         Bweights {
-            w: falloff / 2f32.sqrt(),
-            x: falloff * pos[0] / dist,
-            y: falloff * pos[1] / dist,
-            z: falloff * pos[2] / dist,
+            components: [
+                // 0th order
+                1.0 / 2f32.sqrt(),
+                // 1st order
+                x,
+                y,
+                z,
+                // 2nd order
+                (3.0 * zz - 1.0) / 2.0,
+                2.0 * x * z,
+                2.0 * y * z,
+                xx - yy,
+                2.0 * x * y,
+                // 3rd order
+                z * (5.0 * zz - 3.0) / 2.0,
+                c_lm * x * (5.0 * zz - 1.0),
+                c_lm * y * (5.0 * zz - 1.0),
+                c_no * z * (xx - yy),
+                c_no * z * 2.0 * x * y,
+                x * (xx - 3.0 * yy),
+                y * (3.0 * xx - yy),
+            ],
         }
     }
 
+    /// Multiply all weights by a gain.
+    pub fn amplify(&mut self, gain: f32) {
+        for c in &mut self.components {
+            *c *= gain;
+        }
+    }
+
+    /// Access the individual weights.
+    pub fn components(&self) -> &[f32; MAX_CHANNELS] {
+        &self.components
+    }
+
+    /// Mutably access the individual weights.
+    pub fn components_mut(&mut self) -> &mut [f32; MAX_CHANNELS] {
+        &mut self.components
+    }
+
+    /// The lowest order that can represent these weights.
+    pub fn order(&self) -> AmbisonicOrder {
+        let n = self
+            .components
+            .iter()
+            .rposition(|&c| c != 0.0)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        [
+            AmbisonicOrder::First,
+            AmbisonicOrder::Second,
+            AmbisonicOrder::Third,
+        ]
+        .iter()
+        .copied()
+        .find(|order| order.channels() >= n)
+        .unwrap_or(AmbisonicOrder::Third)
+    }
+
     /// Compute weights that correspond to a virtual microphone at the listener position.
     ///
     /// It takes a `direction` in which the microphone points (does not need to be normalized), and
@@ -128,12 +475,12 @@ impl Bweights {
             + direction[1] * direction[1]
             + direction[1] * direction[2])
             .sqrt();
-        Bweights {
-            w: p * 2f32.sqrt(),
-            x: direction[0] * (1.0 - p) / l,
-            y: direction[1] * (1.0 - p) / l,
-            z: direction[2] * (1.0 - p) / l,
-        }
+        Bweights::new(
+            p * 2f32.sqrt(),
+            direction[0] * (1.0 - p) / l,
+            direction[1] * (1.0 - p) / l,
+            direction[2] * (1.0 - p) / l,
+        )
     }
 
     /// Dot product of *B-format* weights and sample.
@@ -141,7 +488,11 @@ impl Bweights {
     /// If the weights correspond to a virtual microphone, the result is the signal recorded by that
     /// microphone.
     pub fn dot(&self, b: Bformat) -> f32 {
-        self.w * b.w + self.x * b.x + self.y * b.y + self.z * b.z
+        self.components
+            .iter()
+            .zip(&b.components)
+            .map(|(w, c)| w * c)
+            .sum()
     }
 
     /// Produce a *B-format* sample by scaling weights.
@@ -149,46 +500,151 @@ impl Bweights {
     /// If the weights correspond to a sound source, and `s` is the source's current level, the
     /// result is the *B-format* representation of the source.
     pub fn scale(&self, s: f32) -> Bformat {
-        Bformat {
-            w: self.w * s,
-            x: self.x * s,
-            y: self.y * s,
-            z: self.z * s,
+        let mut components = self.components;
+        for c in &mut components {
+            *c *= s;
         }
+        Bformat { components }
     }
 
     /// adjust weights towards target
     pub fn approach(&mut self, target: &Bweights, max_step: f32) {
         // if this turns out too slow we could try to replace it with simple steps along each dimension
-        let dir = [
-            target.w - self.w,
-            target.x - self.x,
-            target.y - self.y,
-            target.z - self.z,
-        ];
-        let dist = (dir[0] * dir[0] + dir[1] * dir[1] + dir[2] * dir[2] + dir[3] * dir[3]).sqrt();
+        let mut dir = [0.0; MAX_CHANNELS];
+        for ((d, t), s) in dir.iter_mut().zip(&target.components).zip(&self.components) {
+            *d = t - s;
+        }
+        let dist = dir.iter().map(|d| d * d).sum::<f32>().sqrt();
         if dist <= max_step {
             *self = *target;
         } else {
             let d = max_step / dist;
-            self.w += dir[0] * d;
-            self.x += dir[1] * d;
-            self.y += dir[2] * d;
-            self.z += dir[3] * d;
+            for (s, dc) in self.components.iter_mut().zip(&dir) {
+                *s += dc * d;
+            }
+        }
+    }
+}
+
+impl Add for Bweights {
+    type Output = Bweights;
+
+    fn add(mut self, other: Bweights) -> Bweights {
+        for (c, o) in self.components.iter_mut().zip(&other.components) {
+            *c += o;
         }
+        self
     }
 }
 
+impl Mul<f32> for Bweights {
+    type Output = Bweights;
+
+    fn mul(mut self, gain: f32) -> Bweights {
+        self.amplify(gain);
+        self
+    }
+}
+
+/// Collect weights from an iterator.
+///
+/// The iterator must yield exactly as many values as there are components in one of the
+/// supported orders (4, 9, or 16).
 impl FromIterator<f32> for Bweights {
     fn from_iter<T: IntoIterator<Item = f32>>(iter: T) -> Self {
-        let mut iter = iter.into_iter();
-        let bw = Bweights {
-            w: iter.next().unwrap(),
-            x: iter.next().unwrap(),
-            y: iter.next().unwrap(),
-            z: iter.next().unwrap(),
-        };
-        assert!(iter.next().is_none());
-        bw
+        let mut components = [0.0; MAX_CHANNELS];
+        let mut n = 0;
+        for x in iter {
+            assert!(n < MAX_CHANNELS, "too many B-format weights");
+            components[n] = x;
+            n += 1;
+        }
+        assert!(
+            AmbisonicOrder::from_channels(n).is_some(),
+            "invalid number of B-format weights: {}",
+            n
+        );
+        Bweights { components }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn first_order_encoding_is_unchanged() {
+        let bw = Bweights::from_position([0.0, 2.0, 0.0], &DistanceModel::default());
+        assert_close(bw.components[0], 0.5 / 2f32.sqrt());
+        assert_close(bw.components[1], 0.0);
+        assert_close(bw.components[2], 0.5);
+        assert_close(bw.components[3], 0.0);
+    }
+
+    #[test]
+    fn higher_order_components_have_unit_maximum() {
+        // Furse-Malham normalization scales every component to a maximum of 1
+        let right = Bweights::from_direction([1.0, 0.0, 0.0]);
+        assert_close(right.components[7], 1.0); // U
+        assert_close(right.components[14], 1.0); // P
+
+        let up = Bweights::from_direction([0.0, 0.0, 1.0]);
+        assert_close(up.components[4], 1.0); // R
+        assert_close(up.components[9], 1.0); // K
+    }
+
+    #[test]
+    fn truncation_removes_higher_orders() {
+        let bw = Bweights::from_direction([1.0, 2.0, 3.0]);
+        assert_eq!(bw.order(), AmbisonicOrder::Third);
+
+        let mut b = bw.scale(1.0);
+        b.truncate(AmbisonicOrder::Second);
+        assert!(b.components()[4..9].iter().all(|&c| c != 0.0));
+        assert!(b.components()[9..].iter().all(|&c| c == 0.0));
+    }
+
+    #[test]
+    fn sample_arithmetic_is_componentwise() {
+        let a = Bweights::from_direction([1.0, 2.0, 3.0]).scale(1.0);
+        let b = Bweights::from_direction([-3.0, 0.5, 1.0]).scale(2.0);
+
+        let mut sum = a + b;
+        sum -= b;
+        for (x, y) in sum.components().iter().zip(a.components()) {
+            assert_close(*x, *y);
+        }
+
+        assert_eq!((a * 2.0).w(), 2.0 * a.w());
+        assert_eq!(a - a, Bformat::zero());
+        assert_eq!(-a + a, Bformat::zero());
+    }
+
+    #[test]
+    fn frames_survive_interleaving() {
+        let frames: Vec<Bformat> = (0..5)
+            .map(|i| Bweights::from_direction([1.0, i as f32, 0.0]).scale(i as f32))
+            .collect();
+
+        let source = BformatSource::new(frames.clone().into_iter(), 48000, AmbisonicOrder::Second);
+        assert_eq!(source.channels(), 9);
+
+        let read: Vec<Bformat> = BformatFrames::new(source.amplify(2.0)).collect();
+        assert_eq!(read.len(), frames.len());
+        for (r, f) in read.iter().zip(&frames) {
+            let mut expected = *f * 2.0;
+            expected.truncate(AmbisonicOrder::Second);
+            assert_eq!(*r, expected);
+        }
+    }
+
+    #[test]
+    fn weights_can_be_collected_for_any_order() {
+        let bw: Bweights = (0..9).map(|i| i as f32).collect();
+        assert_eq!(bw.order(), AmbisonicOrder::Second);
     }
 }
@@ -3,24 +3,72 @@
 //! This module provides functionality for dynamically composing sound sources into a 3D sound
 //! scene.
 
-use crate::bformat::Bformat;
-use crate::bstream::{self, Bstream, BstreamConfig, SoundController};
-use rodio::{source::UniformSourceIterator, Sample, Source};
-use std::sync::atomic::{AtomicBool, Ordering};
+use crate::bformat::{AmbisonicOrder, Bformat};
+use crate::bstream::{self, Bfield, Bstream, BstreamBridge, BstreamConfig, SoundController};
+use crate::bus::{self, Bus, BusController, Capacity, Released, FIELD_CAPACITY, STREAM_CAPACITY};
+use crate::listener::{Listener, ListenerState};
+use crate::queue::{self, Receiver, Sender};
+use crate::reverb::{Reverb, ReverbConfig};
+use crate::room::{EarlyReflections, Room};
+use crate::rotation::{BformatRotation, Orientation, SmoothRotation};
+use rodio::{source::UniformSourceIterator, Source};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-/// Construct a 3D sound mixer and associated sound composer.
+/// Number of sources and scene changes that can be pending before the queue overflows
+const COMMAND_CAPACITY: usize = 64;
+
+/// Number of finished sources and replaced effects that can wait to be freed on the control side
+const RELEASE_CAPACITY: usize = 256;
+
+/// Number of named buses for which the mixer initially reserves room
+///
+/// The composer sends larger storage before they run out, so that adding them does not allocate
+/// on the audio thread.
+const BUS_CAPACITY: usize = 16;
+
+/// Construct a first-order 3D sound mixer and associated sound composer.
 pub fn bmixer(sample_rate: u32) -> (BstreamMixer, Arc<BmixerComposer>) {
+    bmixer_with_order(sample_rate, AmbisonicOrder::First)
+}
+
+/// Construct a 3D sound mixer of given ambisonic order and associated sound composer.
+pub fn bmixer_with_order(
+    sample_rate: u32,
+    order: AmbisonicOrder,
+) -> (BstreamMixer, Arc<BmixerComposer>) {
+    let (release_sender, released) = queue::channel(RELEASE_CAPACITY);
+    let (master, master_controller) = Bus::new(sample_rate, release_sender.clone());
+    let (sender, receiver) = queue::channel(COMMAND_CAPACITY);
+
     let controller = Arc::new(BmixerComposer {
         sample_rate,
-        pending_streams: Mutex::new(Vec::new()),
-        has_pending: AtomicBool::new(false),
+        commands: sender,
+        release_sender: release_sender.clone(),
+        released: Mutex::new(released),
+        scene: Mutex::new(Scene {
+            room: None,
+            streams: Vec::new(),
+            fields: Vec::new(),
+            bus_capacity: Capacity::new(BUS_CAPACITY),
+            capacities: Vec::new(),
+        }),
+        master: master_controller,
+        buses: Mutex::new(Vec::new()),
+        listener: Mutex::new(ListenerState::default()),
     });
 
     let mixer = BstreamMixer {
         controller: controller.clone(),
-        active_streams: Vec::with_capacity(8),
+        commands: receiver,
+        released: release_sender,
+        master,
+        buses: Vec::with_capacity(BUS_CAPACITY),
+        listener: ListenerState::default(),
+        order,
+        rotation: SmoothRotation::new(sample_rate),
+        frame: Bformat::zero(),
+        position: order.channels(),
     };
 
     (mixer, controller)
@@ -28,11 +76,110 @@ pub fn bmixer(sample_rate: u32) -> (BstreamMixer, Arc<BmixerComposer>) {
 
 /// Combine all currently playing 3D sound sources into a single *B-format* stream.
 ///
-/// The mixer implements `rodio::Source<Item = Bformat>`, which must be passed to a renderer before
-/// playback in a `rodio::Sink`.
+/// Sources are mixed on their bus, and all buses are mixed on the master bus.
+///
+/// The mixer implements `rodio::Source<Item = f32>` with one interleaved channel per *B-format*
+/// component of its order, which must be passed to a renderer before playback in a
+/// `rodio::Sink`. Use `next_frame` to obtain whole *B-format* frames instead.
 pub struct BstreamMixer {
     controller: Arc<BmixerComposer>,
-    active_streams: Vec<Bstream>,
+    commands: Receiver<Command>,
+    released: Sender<Released>,
+    master: Bus,
+    buses: Vec<Bus>,
+    listener: ListenerState,
+    order: AmbisonicOrder,
+    rotation: SmoothRotation,
+    frame: Bformat,
+    position: usize,
+}
+
+impl BstreamMixer {
+    /// Ambisonic order of the mixed *B-format* stream.
+    pub fn order(&self) -> AmbisonicOrder {
+        self.order
+    }
+
+    /// Mix the next *B-format* frame.
+    ///
+    /// Components above the mixer's order are zero. Do not mix this with reading samples through
+    /// `Iterator::next`, unless at frame boundaries.
+    pub fn next_frame(&mut self) -> Bformat {
+        // of the scene changes, only the latest ones are applied
+        let mut room = None;
+        let mut listener = None;
+        let mut rotation = None;
+
+        while let Some(cmd) = self.commands.recv() {
+            match cmd {
+                Command::AddStream(mut stream, bus) => {
+                    stream.set_listener(&self.listener);
+                    self.bus_mut(bus).add_stream(stream);
+                }
+                Command::AddField(field, bus) => self.bus_mut(bus).add_field(field),
+                Command::AddBus(bus) => self.buses.push(bus),
+                Command::ReserveStreams(storage, bus) => self.bus_mut(bus).reserve_streams(storage),
+                Command::ReserveFields(storage, bus) => self.bus_mut(bus).reserve_fields(storage),
+                Command::ReserveBuses(storage) => {
+                    let old = bus::regrow(&mut self.buses, storage);
+                    bus::release(&self.released, Released::Buses(old));
+                }
+                Command::SetReverb(reverb) => self.master.set_reverb(reverb),
+                Command::SetRoom(new_room, pool) => {
+                    if let Some((_, unused)) = room.replace((new_room, pool)) {
+                        bus::release(&self.released, Released::Reflections(unused));
+                    }
+                }
+                Command::SetListener(new_listener) => listener = Some(new_listener),
+                Command::SetRotation(new_rotation, is_identity) => {
+                    rotation = Some((new_rotation, is_identity))
+                }
+            }
+        }
+
+        if let Some((room, mut pool)) = room {
+            self.master.set_room(room.as_ref(), &mut pool);
+            for bus in &mut self.buses {
+                bus.set_room(room.as_ref(), &mut pool);
+            }
+            bus::release(&self.released, Released::Reflections(pool));
+        }
+
+        if let Some(listener) = listener {
+            self.listener = listener;
+            self.master.set_listener(&listener);
+            for bus in &mut self.buses {
+                bus.set_listener(&listener);
+            }
+        }
+
+        if let Some((rotation, is_identity)) = rotation {
+            self.rotation.set_target(rotation, is_identity);
+        }
+
+        let mut submix = Bformat::zero();
+        let mut send = Bformat::zero();
+        if !self.master.is_paused() {
+            for bus in &mut self.buses {
+                let (x, s) = bus.next_frame(Bformat::zero(), Bformat::zero());
+                submix += x;
+                send += s;
+            }
+        }
+
+        let (mut mix, _) = self.master.next_frame(submix, send);
+
+        mix.truncate(self.order);
+        self.rotation.process(mix, self.order)
+    }
+
+    /// The bus with given index, or the master bus
+    fn bus_mut(&mut self, index: Option<usize>) -> &mut Bus {
+        match index {
+            Some(i) => &mut self.buses[i],
+            None => &mut self.master,
+        }
+    }
 }
 
 impl Source for BstreamMixer {
@@ -43,7 +190,7 @@ impl Source for BstreamMixer {
 
     #[inline(always)]
     fn channels(&self) -> u16 {
-        1 // actually 4, but they are packed into one struct
+        self.order.channels() as u16
     }
 
     #[inline(always)]
@@ -58,42 +205,78 @@ impl Source for BstreamMixer {
 }
 
 impl Iterator for BstreamMixer {
-    type Item = Bformat;
+    type Item = f32;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.controller.has_pending.load(Ordering::SeqCst) {
-            let mut pending = self
-                .controller
-                .pending_streams
-                .lock()
-                .expect("Cannot lock pending streams");
-            self.active_streams.extend(pending.drain(..));
-            self.controller.has_pending.store(false, Ordering::SeqCst);
+        if self.position == self.order.channels() {
+            self.frame = self.next_frame();
+            self.position = 0;
         }
+        let x = self.frame.components()[self.position];
+        self.position += 1;
+        Some(x)
+    }
+}
 
-        let mut mix = Bformat::zero_value();
-
-        let mut done = Vec::new();
+/// Sources, buses, and scene changes sent to the mixer
+///
+/// Sources are not boxed, so that the mixer does not free the boxes on the audio thread.
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    AddStream(Bstream, Option<usize>),
+    AddField(Bfield, Option<usize>),
+    AddBus(Bus),
+    /// Larger storage for the sources or sound fields of a bus, or for the named buses
+    ReserveStreams(Vec<Bstream>, Option<usize>),
+    ReserveFields(Vec<Bfield>, Option<usize>),
+    ReserveBuses(Vec<Bus>),
+    SetReverb(Option<Reverb>),
+    /// The room and reflections for all sources that may be playing
+    SetRoom(Option<Room>, Vec<Option<EarlyReflections>>),
+    SetListener(ListenerState),
+    SetRotation(BformatRotation, bool),
+}
 
-        for (i, stream) in self.active_streams.iter_mut().enumerate() {
-            match stream.next() {
-                Some(x) => mix = mix.saturating_add(x),
-                None => done.push(i),
-            }
-        }
+/// Room of the scene, and the sources and buses of the mixer
+struct Scene {
+    room: Option<Room>,
+    /// Sources that may still be playing, with their speed of sound and bus
+    streams: Vec<(Arc<BstreamBridge>, f32, Option<usize>)>,
+    /// Sound fields that may still be playing, with their bus
+    fields: Vec<(Arc<BstreamBridge>, Option<usize>)>,
+    /// Capacity the mixer has reserved for named buses
+    bus_capacity: Capacity,
+    /// Capacity the mixer has reserved for the sources and sound fields of the master bus (first)
+    /// and of each named bus
+    capacities: Vec<(Capacity, Capacity)>,
+}
 
-        for i in done.into_iter().rev() {
-            self.active_streams.remove(i);
+impl Scene {
+    /// Capacity reserved for the sources and sound fields of a bus
+    fn capacities(&mut self, bus: Option<usize>) -> &mut (Capacity, Capacity) {
+        let i = bus.map_or(0, |i| i + 1);
+        if i >= self.capacities.len() {
+            self.capacities.resize_with(i + 1, || {
+                (
+                    Capacity::new(STREAM_CAPACITY),
+                    Capacity::new(FIELD_CAPACITY),
+                )
+            });
         }
-
-        Some(mix)
+        &mut self.capacities[i]
     }
 }
 
 /// Compose the 3D sound scene
 pub struct BmixerComposer {
-    has_pending: AtomicBool,
-    pending_streams: Mutex<Vec<Bstream>>,
+    commands: Sender<Command>,
+    /// Handed to new buses, which release their finished sources through it
+    release_sender: Sender<Released>,
+    released: Mutex<Receiver<Released>>,
+    scene: Mutex<Scene>,
+    master: BusController,
+    buses: Mutex<Vec<(String, BusController)>>,
+    listener: Mutex<ListenerState>,
     sample_rate: u32,
 }
 
@@ -101,10 +284,19 @@ impl BmixerComposer {
     /// Add a single-channel `Source` to the sound scene at a position relative to the listener
     ///
     /// Returns a controller object that can be used to control the source during playback.
-    pub fn play<I>(&self, input: I, config: BstreamConfig) -> SoundController
+    pub fn play<I>(&self, input: I, mut config: BstreamConfig) -> SoundController
     where
         I: Source<Item = f32> + Send + 'static,
     {
+        self.free_released();
+        let bus = config.bus.as_deref().map(|name| self.bus_index(name));
+        config.listener = self.listener_state();
+
+        // the source is built with the current room, which does not change until it is added
+        let mut scene = self.scene.lock().expect("Cannot lock scene");
+        config.room = scene.room;
+        let speed_of_sound = config.speed_of_sound;
+
         let (bstream, sound_ctl) = if input.sample_rate() == self.sample_rate {
             bstream::bstream(input, config)
         } else {
@@ -112,12 +304,159 @@ impl BmixerComposer {
             bstream::bstream(input, config)
         };
 
-        self.pending_streams
-            .lock()
-            .expect("Cannot lock pending streams")
-            .push(bstream);
-        self.has_pending.store(true, Ordering::SeqCst);
+        scene.streams.retain(|(bridge, ..)| !bridge.stopped());
+        let playing = scene.streams.iter().filter(|s| s.2 == bus).count();
+        // besides the new source, one that has just stopped may not have been removed yet
+        if let Some(storage) = scene.capacities(bus).0.grow(playing + 2) {
+            self.commands.send(Command::ReserveStreams(storage, bus));
+        }
+        scene
+            .streams
+            .push((sound_ctl.bridge().clone(), speed_of_sound, bus));
+        self.commands.send(Command::AddStream(bstream, bus));
 
         sound_ctl
     }
+
+    /// Add an interleaved *B-format* `Source` to the sound scene, such as a recorded ambience.
+    ///
+    /// The source's channels are the *B-format* components in Furse-Malham order. The sound
+    /// field is mixed unchanged (but rotated with the listener). Returns a controller object
+    /// that can be used to pause or stop the sound field during playback.
+    pub fn play_bformat<I>(&self, input: I) -> SoundController
+    where
+        I: Source<Item = f32> + Send + 'static,
+    {
+        self.play_bformat_with_config(input, BstreamConfig::new())
+    }
+
+    /// Add an interleaved *B-format* `Source` to the sound scene with the given configuration.
+    ///
+    /// Only the gain and bus of the configuration apply to sound fields.
+    pub fn play_bformat_with_config<I>(&self, input: I, config: BstreamConfig) -> SoundController
+    where
+        I: Source<Item = f32> + Send + 'static,
+    {
+        self.free_released();
+        let bus = config.bus.as_deref().map(|name| self.bus_index(name));
+        let (field, sound_ctl) = bstream::bfield(input, config, self.sample_rate);
+
+        let mut scene = self.scene.lock().expect("Cannot lock scene");
+        scene.fields.retain(|(bridge, _)| !bridge.stopped());
+        let playing = scene.fields.iter().filter(|f| f.1 == bus).count();
+        if let Some(storage) = scene.capacities(bus).1.grow(playing + 2) {
+            self.commands.send(Command::ReserveFields(storage, bus));
+        }
+        scene.fields.push((sound_ctl.bridge().clone(), bus));
+        self.commands.send(Command::AddField(field, bus));
+
+        sound_ctl
+    }
+
+    /// Get the controller of a named bus
+    ///
+    /// The bus is created if it does not exist yet. Its output is mixed into the master bus.
+    pub fn bus(&self, name: &str) -> BusController {
+        let index = self.bus_index(name);
+        self.buses.lock().expect("Cannot lock buses")[index]
+            .1
+            .clone()
+    }
+
+    /// Get the controller of the master bus
+    ///
+    /// The master bus receives all sources that are not assigned to a named bus, and the output
+    /// of all named buses.
+    pub fn master(&self) -> BusController {
+        self.master.clone()
+    }
+
+    /// Set the reverb that is applied to the sources' reverb sends, or disable it
+    ///
+    /// The reverb is mixed into the master bus. A new reverb starts without a tail.
+    pub fn set_reverb(&self, config: Option<ReverbConfig>) {
+        self.free_released();
+        let reverb = config.map(|config| Reverb::new(&config, self.sample_rate));
+        self.commands.send(Command::SetReverb(reverb));
+    }
+
+    /// Set the room that produces early reflections of all sources, or remove it
+    ///
+    /// The reflections of every source are prepared here rather than on the audio thread.
+    pub fn set_room(&self, room: Option<Room>) {
+        self.free_released();
+
+        let mut scene = self.scene.lock().expect("Cannot lock scene");
+        scene.streams.retain(|(bridge, ..)| !bridge.stopped());
+        let pool = scene
+            .streams
+            .iter()
+            .map(|&(_, speed_of_sound, _)| {
+                room.map(|room| EarlyReflections::new(room, self.sample_rate, speed_of_sound))
+            })
+            .collect();
+
+        scene.room = room;
+        self.commands.send(Command::SetRoom(room, pool));
+    }
+
+    /// Index of a named bus, which is created if necessary
+    fn bus_index(&self, name: &str) -> usize {
+        let mut buses = self.buses.lock().expect("Cannot lock buses");
+        if let Some(i) = buses.iter().position(|(n, _)| n == name) {
+            return i;
+        }
+
+        let (bus, controller) = Bus::new(self.sample_rate, self.release_sender.clone());
+        let mut scene = self.scene.lock().expect("Cannot lock scene");
+        if let Some(storage) = scene.bus_capacity.grow(buses.len() + 1) {
+            self.commands.send(Command::ReserveBuses(storage));
+        }
+        self.commands.send(Command::AddBus(bus));
+
+        buses.push((name.to_string(), controller));
+        buses.len() - 1
+    }
+
+    /// Get the controller of the listener of world-space sources
+    pub fn listener(self: &Arc<Self>) -> Listener {
+        Listener::new(self.clone())
+    }
+
+    /// Current position and velocity of the listener
+    pub(crate) fn listener_state(&self) -> ListenerState {
+        *self.listener.lock().expect("Cannot lock listener")
+    }
+
+    /// Change the position or velocity of the listener
+    pub(crate) fn update_listener(&self, update: impl FnOnce(&mut ListenerState)) {
+        self.free_released();
+        let mut listener = self.listener.lock().expect("Cannot lock listener");
+        update(&mut listener);
+
+        self.commands.send(Command::SetListener(*listener));
+    }
+
+    /// Free the sources and effects that the mixer has finished with
+    ///
+    /// Called whenever the scene changes, so that they do not pile up. If another thread is
+    /// already freeing them, there is nothing to do.
+    fn free_released(&self) {
+        if let Ok(mut released) = self.released.try_lock() {
+            while released.recv().is_some() {}
+        }
+    }
+
+    /// Set the orientation of the listener
+    ///
+    /// The mixed sound field is rotated so that source positions, which are relative to the
+    /// listener's position, need not be updated when the listener turns.
+    pub fn set_listener_orientation(&self, orientation: Orientation) {
+        let rotation = BformatRotation::from_orientation(&orientation);
+        let is_identity = orientation == Orientation::identity();
+        self.free_released();
+
+        self.commands
+            .send(Command::SetRotation(rotation, is_identity));
+    }
 }
@@ -1,12 +1,33 @@
 //! Represent audio sources in *B-format*.
 
-use crate::bformat::{Bformat, Bweights};
+use crate::absorption::{AirAbsorption, Lowpass};
+use crate::bformat::{norm, AmbisonicOrder, Bformat, BformatFrames, BformatSource, Bweights};
 use crate::constants::SPEED_OF_SOUND;
-use rodio::{Sample, Source};
+use crate::delay::DelayLine;
+use crate::directivity::Directivity;
+use crate::distance::DistanceModel;
+use crate::listener::ListenerState;
+use crate::nearfield::NearField;
+use crate::occlusion::Occlusion;
+use crate::queue::{self, Receiver, Sender};
+use crate::resampler::{Interpolation, Resampler};
+use crate::room::{EarlyReflections, Room};
+use crate::spread::Spread;
+use rodio::source::UniformSourceIterator;
+use rodio::Source;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::Duration;
 
+/// Default limit of the propagation delay, which sound covers in about 690 m
+const DEFAULT_MAX_PROPAGATION_DELAY: Duration = Duration::from_secs(2);
+
+/// Number of commands that can be pending for a source before the queue overflows
+const COMMAND_CAPACITY: usize = 64;
+
+/// Duration of the short fade that avoids clicks when the gain changes or a source is muted
+pub(crate) const DECLICK_DURATION: Duration = Duration::from_millis(5);
+
 /// Convert a `rodio::Source` to a spatial `Bstream` source with associated controller
 ///
 /// The input source must produce `f32` samples and is expected to have exactly one channel.
@@ -17,37 +38,118 @@ pub fn bstream<I: Source<Item = f32> + Send + 'static>(
     assert_eq!(source.channels(), 1);
 
     let bridge = Arc::new(BstreamBridge {
-        commands: Mutex::new(Vec::new()),
-        pending_commands: AtomicBool::new(false),
         stopped: AtomicBool::new(false),
     });
+    let (sender, receiver) = queue::channel(COMMAND_CAPACITY);
+
+    let geometry = Geometry {
+        position: config.position,
+        velocity: config.velocity,
+        direction: config.direction,
+        directivity: config.directivity,
+        spread: config.spread,
+        doppler_factor: config.doppler_factor,
+    };
 
-    let (position, weights) = match config.position {
-        Some(p) => (p, Bweights::from_position(p)),
-        None => ([0.0, 0.0, 0.0], Bweights::omni_source()),
+    // sources that are not in world space have their own listener at the origin
+    let listener = if config.world_space {
+        config.listener
+    } else {
+        ListenerState {
+            position: [0.0, 0.0, 0.0],
+            velocity: config.listener_velocity,
+        }
+    };
+
+    let relative_position = geometry.relative_position(&listener);
+    let position = relative_position.unwrap_or([0.0, 0.0, 0.0]);
+    let (weights, send_weights) = geometry.weights(&listener, &config.distance_model);
+    let speed = geometry.doppler_rate(&listener, config.speed_of_sound);
+    let receding_speed = geometry.receding_speed(&listener, config.speed_of_sound);
+
+    let cutoff = relative_position.and_then(|p| config.air_absorption.cutoff(norm(p)));
+
+    // with a propagation delay, the delay line produces the Doppler effect
+    let mut resampler = Resampler::new(
+        config.interpolation,
+        if config.propagation_delay { 1.0 } else { speed },
+    );
+    for _ in 0..resampler.lookahead() {
+        resampler.push(source.next().unwrap_or(0.0));
+    }
+
+    let propagation = if config.propagation_delay {
+        let delay = propagation_delay(
+            norm(position),
+            receding_speed,
+            config.speed_of_sound,
+            source.sample_rate(),
+        );
+        let max_delay = config.max_propagation_delay.as_secs_f32() * source.sample_rate() as f32;
+        let mut line = DelayLine::new(delay, max_delay, config.interpolation);
+        line.set_slope(delay_slope(speed));
+        Some(line)
+    } else {
+        None
     };
 
+    let reflections = config.room.map(|room| {
+        let mut reflections =
+            EarlyReflections::new(room, source.sample_rate(), config.speed_of_sound);
+        if relative_position.is_some() {
+            reflections.set_source(position, &config.distance_model);
+        }
+        reflections
+    });
+
+    let near_field = config.near_field.map(|radius| {
+        let mut near_field = NearField::new(radius, source.sample_rate(), config.speed_of_sound);
+        if relative_position.is_some() {
+            near_field.set_distance(norm(position));
+        }
+        near_field
+    });
+
     let controller = SoundController {
         bridge: bridge.clone(),
-        position,
-        velocity: config.velocity,
-        doppler_factor: config.doppler_factor,
+        commands: sender,
+        gain: config.gain,
+        muted: false,
+        geometry,
+        world_space: config.world_space,
+        listener_velocity: config.listener_velocity,
         speed_of_sound: config.speed_of_sound,
+        distance_model: config.distance_model,
     };
 
     let stream = Bstream {
+        fader: Fader::new(config.gain, source.sample_rate()),
+        reverb_send: Fader::new(config.reverb_send, source.sample_rate()),
+        send_gain: config.reverb_send,
+        send: Bformat::zero(),
+        occlusion: Occlusion::new(source.sample_rate()),
+        obstruction: Occlusion::new(source.sample_rate()),
+        sample_rate: source.sample_rate(),
+        position: relative_position,
+        world: config.world_space.then_some(World { geometry, listener }),
+        lowpass: Lowpass::new(cutoff, source.sample_rate()),
+        air_absorption: config.air_absorption,
+        distance_model: controller.distance_model.clone(),
+        speed_of_sound: config.speed_of_sound,
+        reflections,
+        near_field,
         bweights: weights,
         target_weights: weights,
-        speed: compute_doppler_rate(
-            position,
-            config.velocity,
-            config.doppler_factor,
-            config.speed_of_sound,
-        ),
+        send_weights,
+        target_send_weights: send_weights,
+        speed,
+        receding_speed,
+        propagation,
+        drain: None,
         sampling_offset: 0.0,
-        previous_sample: source.next().unwrap_or(0.0),
-        next_sample: source.next().unwrap_or(0.0),
+        resampler,
         bridge,
+        commands: receiver,
         input: Box::new(source),
         paused: false,
     };
@@ -55,21 +157,106 @@ pub fn bstream<I: Source<Item = f32> + Send + 'static>(
     (stream, controller)
 }
 
+/// Convert an interleaved *B-format* `rodio::Source` to a `Bfield` sound field with associated
+/// controller
+///
+/// The source's channels are the *B-format* components in Furse-Malham order (see
+/// `BformatFrames`). It is resampled to `sample_rate` if necessary. Only the gain of the
+/// configuration applies to sound fields.
+pub fn bfield<I: Source<Item = f32> + Send + 'static>(
+    source: I,
+    config: BstreamConfig,
+    sample_rate: u32,
+) -> (Bfield, SoundController) {
+    let bridge = Arc::new(BstreamBridge {
+        stopped: AtomicBool::new(false),
+    });
+    let (sender, receiver) = queue::channel(COMMAND_CAPACITY);
+
+    let controller = SoundController {
+        bridge: bridge.clone(),
+        commands: sender,
+        gain: config.gain,
+        muted: false,
+        geometry: Geometry {
+            position: None,
+            velocity: config.velocity,
+            direction: config.direction,
+            directivity: config.directivity,
+            spread: config.spread,
+            doppler_factor: config.doppler_factor,
+        },
+        world_space: false,
+        listener_velocity: config.listener_velocity,
+        speed_of_sound: config.speed_of_sound,
+        distance_model: config.distance_model,
+    };
+
+    let input: Box<dyn Source<Item = f32> + Send> = if source.sample_rate() == sample_rate {
+        Box::new(source)
+    } else {
+        let channels = source.channels();
+        Box::new(UniformSourceIterator::new(source, channels, sample_rate))
+    };
+
+    let field = Bfield {
+        input: BformatFrames::new(input),
+        fader: Fader::new(config.gain, sample_rate),
+        bridge,
+        commands: receiver,
+        paused: false,
+    };
+
+    (field, controller)
+}
+
 /// Initial configuration for constructing `Bstream`s
 pub struct BstreamConfig {
     position: Option<[f32; 3]>,
     velocity: [f32; 3],
+    listener_velocity: [f32; 3],
     doppler_factor: f32,
-    speed_of_sound: f32,
+    pub(crate) speed_of_sound: f32,
+    distance_model: DistanceModel,
+    direction: [f32; 3],
+    directivity: Directivity,
+    spread: Spread,
+    gain: f32,
+    reverb_send: f32,
+    air_absorption: AirAbsorption,
+    propagation_delay: bool,
+    max_propagation_delay: Duration,
+    interpolation: Interpolation,
+    near_field: Option<f32>,
+    world_space: bool,
+    pub(crate) listener: ListenerState,
+    pub(crate) room: Option<Room>,
+    pub(crate) bus: Option<String>,
 }
 
 impl Default for BstreamConfig {
     fn default() -> Self {
         BstreamConfig {
+            gain: 1.0,
+            reverb_send: 1.0,
+            air_absorption: AirAbsorption::default(),
+            propagation_delay: false,
+            max_propagation_delay: DEFAULT_MAX_PROPAGATION_DELAY,
+            interpolation: Interpolation::default(),
+            near_field: None,
+            world_space: false,
+            listener: ListenerState::default(),
+            room: None,
+            bus: None,
             position: None,
             velocity: [0.0, 0.0, 0.0],
+            listener_velocity: [0.0, 0.0, 0.0],
             doppler_factor: 1.0,
             speed_of_sound: SPEED_OF_SOUND,
+            distance_model: DistanceModel::default(),
+            direction: [0.0, 0.0, 0.0],
+            directivity: Directivity::default(),
+            spread: Spread::default(),
         }
     }
 }
@@ -92,6 +279,15 @@ impl BstreamConfig {
         self
     }
 
+    /// Set initial velocity of the listener.
+    ///
+    /// Only applies to sources that are not in world space, which take the velocity of the
+    /// scene's `Listener` instead.
+    pub fn with_listener_velocity(mut self, v: [f32; 3]) -> Self {
+        self.listener_velocity = v;
+        self
+    }
+
     /// Set doppler factor for this stream.
     pub fn with_doppler_factor(mut self, d: f32) -> Self {
         self.doppler_factor = d;
@@ -103,47 +299,302 @@ impl BstreamConfig {
         self.speed_of_sound = s;
         self
     }
+
+    /// Set how the stream is attenuated with distance.
+    pub fn with_distance_model(mut self, model: DistanceModel) -> Self {
+        self.distance_model = model;
+        self
+    }
+
+    /// Set the direction the source faces, relative to the listener's orientation (defaults to
+    /// none).
+    ///
+    /// Together with the directivity, the direction determines how loud the source is in the
+    /// direction of the listener. Sources without a direction radiate equally in all
+    /// directions.
+    pub fn with_direction(mut self, direction: [f32; 3]) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Set the pattern with which the source radiates around its direction (defaults to
+    /// `Directivity::omni()`).
+    ///
+    /// The directivity applies to the direct sound only; reflections and reverb are not
+    /// affected.
+    pub fn with_directivity(mut self, directivity: Directivity) -> Self {
+        self.directivity = directivity;
+        self
+    }
+
+    /// Set the spatial extent of the source (defaults to `Spread::point()`).
+    pub fn with_spread(mut self, spread: Spread) -> Self {
+        self.spread = spread;
+        self
+    }
+
+    /// Set initial gain (defaults to 1).
+    ///
+    /// Start with a gain of 0 and use `SoundController::fade_to` to fade the stream in.
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    /// Set how the stream is filtered by the air with distance.
+    pub fn with_air_absorption(mut self, model: AirAbsorption) -> Self {
+        self.air_absorption = model;
+        self
+    }
+
+    /// Delay the stream by the time its sound takes to reach the listener (defaults to off).
+    ///
+    /// With a propagation delay, the Doppler effect results from the changing delay, and the
+    /// velocity extrapolates the delay between position updates and accounts for the distance
+    /// the source has moved since the sound left it. The delay is limited by
+    /// `with_max_propagation_delay`.
+    pub fn with_propagation_delay(mut self, enabled: bool) -> Self {
+        self.propagation_delay = enabled;
+        self
+    }
+
+    /// Set the longest propagation delay of the stream (defaults to 2 seconds).
+    ///
+    /// Each delayed stream allocates a buffer of this length when it is created (about 384 kB
+    /// for 2 seconds at 48 kHz), so that the audio thread never has to. Sources further away
+    /// than sound travels in this time are delayed by the limit only, and while the delay stays
+    /// at the limit, their motion causes no Doppler effect. Distance attenuation and air
+    /// absorption still follow the actual distance.
+    pub fn with_max_propagation_delay(mut self, delay: Duration) -> Self {
+        self.max_propagation_delay = delay;
+        self
+    }
+
+    /// Interpret positions, velocities, and directions in world space (defaults to off).
+    ///
+    /// World-space sources are heard relative to the scene's `Listener`, and the scene updates
+    /// their direction, distance attenuation, and Doppler effect when the listener moves.
+    /// Otherwise, positions are relative to the listener.
+    pub fn with_world_space(mut self, enabled: bool) -> Self {
+        self.world_space = enabled;
+        self
+    }
+
+    /// Compensate the near field of the source inside `radius` (defaults to off).
+    ///
+    /// Sources closer than the radius have their directional components boosted at low
+    /// frequencies, like the curved wave fronts of real close sources. The radius is the
+    /// distance at which the playback is calibrated, e.g. the radius of a loudspeaker ring or
+    /// the distance of HRIRs loaded with `HrtfConfig::from_sofa_at_distance`. The boost is
+    /// limited to sources at a quarter of the radius.
+    pub fn with_near_field(mut self, radius: f32) -> Self {
+        self.near_field = Some(radius);
+        self
+    }
+
+    /// Set the interpolation used to resample the stream for the Doppler effect (defaults to
+    /// `Interpolation::Linear`).
+    ///
+    /// With a propagation delay, the delay line reads fractional delays with this interpolation.
+    /// Cubic and sinc interpolation delay the stream by at least one and seven samples.
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Set how much of the stream is sent to the scene's reverb (defaults to 1).
+    pub fn with_reverb_send(mut self, level: f32) -> Self {
+        self.reverb_send = level;
+        self
+    }
+
+    /// Mix the stream into the named bus instead of the master bus.
+    ///
+    /// The bus is created if it does not exist yet.
+    pub fn with_bus(mut self, name: &str) -> Self {
+        self.bus = Some(name.to_string());
+        self
+    }
 }
 
 /// Spatial source
 ///
-/// Consumes samples from the inner source and converts them to *B-format* samples.
+/// Consumes samples from the inner source and converts them to *B-format* samples. If the scene
+/// has a room, the samples include the source's early reflections.
+///
+/// A `Bstream` iterates over whole *B-format* frames. Unlike in version 0.4, it is not a
+/// `rodio::Source` itself; use `into_source` to play it through `rodio`.
 pub struct Bstream {
     input: Box<dyn Source<Item = f32> + Send>,
     bridge: Arc<BstreamBridge>,
+    commands: Receiver<Command>,
+    fader: Fader,
+    reverb_send: Fader,
+    send_gain: f32,
+    send: Bformat,
+    occlusion: Occlusion,
+    obstruction: Occlusion,
+
+    sample_rate: u32,
+    /// Position relative to the listener
+    position: Option<[f32; 3]>,
+    world: Option<World>,
+    distance_model: DistanceModel,
+    air_absorption: AirAbsorption,
+    lowpass: Lowpass,
+    speed_of_sound: f32,
+    reflections: Option<EarlyReflections>,
+    near_field: Option<NearField>,
 
     bweights: Bweights,
     target_weights: Bweights,
+    /// Weights of the reverb send, which the directivity does not affect
+    send_weights: Bweights,
+    target_send_weights: Bweights,
 
     speed: f32,
+    /// Speed at which the source recedes from the listener, which sets the propagation delay
+    receding_speed: f32,
+    propagation: Option<DelayLine>,
+    drain: Option<usize>,
     sampling_offset: f32,
-    previous_sample: f32,
-    next_sample: f32,
+    resampler: Resampler,
     paused: bool,
 }
 
-impl Bstream {}
+impl Bstream {
+    /// Convert into an interleaved `rodio::Source` with the components up to `order`
+    pub fn into_source(self, order: AmbisonicOrder) -> BformatSource<Self> {
+        let sample_rate = self.sample_rate;
+        BformatSource::new(self, sample_rate, order)
+    }
+
+    /// Switch to the reflections of another room, or remove reflections
+    ///
+    /// The reflections are taken from `pool`, which receives the previous ones in exchange, so
+    /// that nothing is allocated or freed. Sources whose reflections already belong to `room`
+    /// are left alone, as are sources for which the pool has no matching reflections.
+    pub(crate) fn exchange_reflections(
+        &mut self,
+        room: Option<&Room>,
+        pool: &mut [Option<EarlyReflections>],
+    ) {
+        if self.reflections.as_ref().map(EarlyReflections::room) == room {
+            return;
+        }
+
+        let speed_of_sound = self.speed_of_sound;
+        let slot = pool.iter_mut().find(|slot| match (slot.as_ref(), room) {
+            (Some(reflections), Some(room)) => {
+                reflections.room() == room && reflections.speed_of_sound() == speed_of_sound
+            }
+            (None, None) => true,
+            _ => false,
+        });
+
+        if let Some(slot) = slot {
+            std::mem::swap(&mut self.reflections, slot);
+            if let (Some(reflections), Some(position)) = (&mut self.reflections, self.position) {
+                reflections.set_source(position, &self.distance_model);
+            }
+        }
+    }
+
+    /// Move the listener of a world-space source
+    ///
+    /// Has no effect on sources that are not in world space.
+    pub(crate) fn set_listener(&mut self, listener: &ListenerState) {
+        if let Some(world) = &mut self.world {
+            world.listener = *listener;
+            self.update_world(false);
+        }
+    }
 
-impl Source for Bstream {
-    #[inline(always)]
-    fn current_frame_len(&self) -> Option<usize> {
-        self.input.current_frame_len()
+    /// Update the relative geometry of a world-space source
+    fn update_world(&mut self, jump: bool) {
+        let world = match &self.world {
+            Some(world) => world,
+            None => return,
+        };
+
+        let (weights, send_weights) = world
+            .geometry
+            .weights(&world.listener, &self.distance_model);
+        let speed = world
+            .geometry
+            .doppler_rate(&world.listener, self.speed_of_sound);
+        let receding_speed = world
+            .geometry
+            .receding_speed(&world.listener, self.speed_of_sound);
+        let position = world.geometry.relative_position(&world.listener);
+
+        self.target_weights = weights;
+        self.target_send_weights = send_weights;
+        if jump {
+            self.bweights = weights;
+            self.send_weights = send_weights;
+        }
+        self.set_speed(speed, receding_speed);
+        if let Some(p) = position {
+            self.set_position(p);
+        }
     }
 
-    #[inline(always)]
-    fn channels(&self) -> u16 {
-        assert_eq!(self.input.channels(), 1);
-        1
+    fn set_speed(&mut self, speed: f32, receding_speed: f32) {
+        self.speed = speed;
+        self.receding_speed = receding_speed;
+        match &mut self.propagation {
+            Some(line) => line.set_slope(delay_slope(speed)),
+            None => self.resampler.set_speed(speed),
+        }
+    }
+
+    /// Update everything that depends on the position relative to the listener, except the
+    /// weights
+    fn set_position(&mut self, p: [f32; 3]) {
+        self.position = Some(p);
+        if let Some(line) = &mut self.propagation {
+            line.set_target(propagation_delay(
+                norm(p),
+                self.receding_speed,
+                self.speed_of_sound,
+                self.sample_rate,
+            ));
+        }
+        self.lowpass.set_cutoff(self.air_absorption.cutoff(norm(p)));
+        if let Some(near_field) = &mut self.near_field {
+            near_field.set_distance(norm(p));
+        }
+        if let Some(reflections) = &mut self.reflections {
+            reflections.set_source(p, &self.distance_model);
+        }
     }
 
-    #[inline(always)]
-    fn sample_rate(&self) -> u32 {
-        self.input.sample_rate()
+    /// Part of the last sample that is sent to the reverb
+    ///
+    /// The send includes the reflections, but not the obstruction of the direct sound.
+    pub(crate) fn reverb_send(&self) -> Bformat {
+        self.send
     }
 
-    #[inline(always)]
-    fn total_duration(&self) -> Option<Duration> {
-        self.input.total_duration()
+    /// Next sample of the input, followed by silence until the delayed sound has arrived
+    fn next_input(&mut self) -> Option<f32> {
+        match &mut self.drain {
+            Some(0) => None,
+            Some(n) => {
+                *n -= 1;
+                Some(0.0)
+            }
+            None => match self.input.next() {
+                Some(x) => Some(x),
+                None => {
+                    let delay = self.propagation.as_ref().map_or(0, DelayLine::ring_out);
+                    let remaining = (delay + self.resampler.tail()).checked_sub(1)?;
+                    self.drain = Some(remaining);
+                    Some(0.0)
+                }
+            },
+        }
     }
 }
 
@@ -151,41 +602,80 @@ impl Iterator for Bstream {
     type Item = Bformat;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.bridge.pending_commands.load(Ordering::SeqCst) {
-            let mut commands = self.bridge.commands.lock().unwrap();
-
-            for cmd in commands.drain(..) {
-                match cmd {
-                    Command::SetWeights(bw) => self.bweights = bw,
-                    Command::SetTarget(bw) => self.target_weights = bw,
-                    Command::SetSpeed(s) => self.speed = s,
-                    Command::Stop => {
-                        self.bridge.stopped.store(true, Ordering::SeqCst);
-                        return None;
+        self.send = Bformat::zero();
+
+        while let Some(cmd) = self.commands.recv() {
+            match cmd {
+                Command::SetWeights(bw, send) => {
+                    self.bweights = bw;
+                    self.send_weights = send;
+                }
+                Command::SetTarget(bw, send) => {
+                    self.target_weights = bw;
+                    self.target_send_weights = send;
+                }
+                Command::SetSpeed(s, r) => self.set_speed(s, r),
+                Command::SetGeometry(geometry, jump) => {
+                    if let Some(world) = &mut self.world {
+                        world.geometry = geometry;
+                        self.update_world(jump);
                     }
-                    Command::Pause => self.paused = true,
-                    Command::Resume => self.paused = false,
                 }
+                Command::Fade(gain, duration) => self.fader.fade_to(gain, duration),
+                Command::FadeOut(duration) => self.fader.fade_out(duration),
+                Command::SetReverbSend(level) => self.reverb_send.fade_to(level, DECLICK_DURATION),
+                Command::SetPosition(p) => self.set_position(p),
+                Command::SetDistanceModel(model) => {
+                    if let (Some(reflections), Some(p)) = (&mut self.reflections, self.position) {
+                        reflections.set_source(p, &model);
+                    }
+                    self.distance_model = model;
+                }
+                Command::SetAirAbsorption(model) => {
+                    if let Some(p) = self.position {
+                        self.lowpass.set_cutoff(model.cutoff(norm(p)));
+                    }
+                    self.air_absorption = model;
+                }
+                Command::SetOcclusion(amount) => self.occlusion.set_amount(amount),
+                Command::SetObstruction(amount) => self.obstruction.set_amount(amount),
+                Command::Stop => {
+                    self.bridge.stopped.store(true, Ordering::SeqCst);
+                    return None;
+                }
+                Command::Pause => self.paused = true,
+                Command::Resume => self.paused = false,
             }
-
-            self.bridge.pending_commands.store(false, Ordering::SeqCst);
         }
 
         if self.paused {
-            self.bweights = self.target_weights; // during pause we can allow the source to jump
-            return Some(Bformat::zero_value());
+            // a paused source is silent, so it need not fade out before stopping
+            if self.fader.stopping {
+                self.bridge.stopped.store(true, Ordering::SeqCst);
+                return None;
+            }
+            // during pause we can allow the source to jump
+            self.bweights = self.target_weights;
+            self.send_weights = self.target_send_weights;
+            return Some(Bformat::zero());
         }
 
+        let gain = match self.fader.next() {
+            Some(gain) => gain,
+            None => {
+                self.bridge.stopped.store(true, Ordering::SeqCst);
+                return None;
+            }
+        };
+
         // adjusting the weights slowly avoids audio artifacts but prevents very fast position
         // changes
         self.bweights.approach(&self.target_weights, 0.001);
+        self.send_weights.approach(&self.target_send_weights, 0.001);
 
         while self.sampling_offset >= 1.0 {
-            match self.input.next() {
-                Some(x) => {
-                    self.previous_sample = self.next_sample;
-                    self.next_sample = x;
-                }
+            match self.next_input() {
+                Some(x) => self.resampler.push(x),
                 None => {
                     self.bridge.stopped.store(true, Ordering::SeqCst);
                     return None;
@@ -194,92 +684,378 @@ impl Iterator for Bstream {
             self.sampling_offset -= 1.0;
         }
 
-        let x = self.next_sample * self.sampling_offset
-            + self.previous_sample * (1.0 - self.sampling_offset);
+        let x = self.resampler.interpolate(self.sampling_offset);
 
-        self.sampling_offset += self.speed;
-        Some(self.bweights.scale(x))
+        let x = match &mut self.propagation {
+            Some(line) => {
+                self.sampling_offset += 1.0;
+                line.process(x)
+            }
+            None => {
+                self.sampling_offset += self.speed;
+                x
+            }
+        };
+
+        self.send_gain = self.reverb_send.next().unwrap_or(0.0);
+
+        let x = self.lowpass.process(x) * gain;
+        let x = self.occlusion.process(x);
+        let direct = self.obstruction.process(x);
+
+        let mut output = match &mut self.near_field {
+            Some(near_field) => near_field.process(direct, &self.bweights),
+            None => self.bweights.scale(direct),
+        };
+        let mut send = self.send_weights.scale(x);
+        if let Some(reflections) = &mut self.reflections {
+            let reflected = reflections.process(x);
+            output += reflected;
+            send += reflected;
+        }
+        self.send = send * self.send_gain;
+        Some(output)
+    }
+}
+
+/// Sound field source
+///
+/// Passes *B-format* samples from the inner source to the mix unchanged, such as an ambience bed
+/// recorded with an ambisonic microphone. Position, velocity, and distance settings of the
+/// controller have no effect on sound fields.
+pub struct Bfield {
+    input: BformatFrames<Box<dyn Source<Item = f32> + Send>>,
+    bridge: Arc<BstreamBridge>,
+    commands: Receiver<Command>,
+    fader: Fader,
+    paused: bool,
+}
+
+impl Iterator for Bfield {
+    type Item = Bformat;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(cmd) = self.commands.recv() {
+            match cmd {
+                Command::Stop => {
+                    self.bridge.stopped.store(true, Ordering::SeqCst);
+                    return None;
+                }
+                Command::Pause => self.paused = true,
+                Command::Resume => self.paused = false,
+                Command::Fade(gain, duration) => self.fader.fade_to(gain, duration),
+                Command::FadeOut(duration) => self.fader.fade_out(duration),
+                Command::SetWeights(..)
+                | Command::SetTarget(..)
+                | Command::SetSpeed(..)
+                | Command::SetGeometry(..)
+                | Command::SetReverbSend(_)
+                | Command::SetPosition(_)
+                | Command::SetDistanceModel(_)
+                | Command::SetAirAbsorption(_)
+                | Command::SetOcclusion(_)
+                | Command::SetObstruction(_) => {}
+            }
+        }
+
+        if self.paused {
+            if self.fader.stopping {
+                self.bridge.stopped.store(true, Ordering::SeqCst);
+                return None;
+            }
+            return Some(Bformat::zero());
+        }
+
+        let gain = match self.fader.next() {
+            Some(gain) => gain,
+            None => {
+                self.bridge.stopped.store(true, Ordering::SeqCst);
+                return None;
+            }
+        };
+
+        match self.input.next() {
+            Some(x) => Some(x * gain),
+            None => {
+                self.bridge.stopped.store(true, Ordering::SeqCst);
+                None
+            }
+        }
+    }
+}
+
+/// Gain envelope of a source or bus, advanced once per output sample
+pub(crate) struct Fader {
+    sample_rate: u32,
+    gain: f32,
+    target: f32,
+    step: f32,
+    remaining: u32,
+    stopping: bool,
+}
+
+impl Fader {
+    pub(crate) fn new(gain: f32, sample_rate: u32) -> Self {
+        Fader {
+            sample_rate,
+            gain,
+            target: gain,
+            step: 0.0,
+            remaining: 0,
+            stopping: false,
+        }
+    }
+
+    /// Linearly approach `target` over `duration`
+    ///
+    /// Has no effect while fading out to stop.
+    pub(crate) fn fade_to(&mut self, target: f32, duration: Duration) {
+        if self.stopping {
+            return;
+        }
+        let n = (duration.as_secs_f32() * self.sample_rate as f32).round() as u32;
+        self.target = target;
+        self.remaining = n;
+        if n == 0 {
+            self.gain = target;
+        } else {
+            self.step = (target - self.gain) / n as f32;
+        }
+    }
+
+    /// Whether the gain has reached zero
+    pub(crate) fn is_silent(&self) -> bool {
+        self.remaining == 0 && self.gain == 0.0
+    }
+
+    /// Fade to silence over `duration`, then stop
+    ///
+    /// A later fade out replaces the duration of an earlier one.
+    fn fade_out(&mut self, duration: Duration) {
+        self.stopping = false;
+        self.fade_to(0.0, duration);
+        self.stopping = true;
+    }
+
+    /// Gain for the next sample, or `None` if the source has faded out
+    pub(crate) fn next(&mut self) -> Option<f32> {
+        if self.stopping && self.remaining == 0 {
+            return None;
+        }
+
+        let gain = self.gain;
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.gain = if self.remaining == 0 {
+                self.target
+            } else {
+                self.gain + self.step
+            };
+        }
+        Some(gain)
     }
 }
 
-#[derive(Debug)]
 enum Command {
-    SetWeights(Bweights),
-    SetTarget(Bweights),
-    SetSpeed(f32),
+    SetWeights(Bweights, Bweights),
+    SetTarget(Bweights, Bweights),
+    SetSpeed(f32, f32),
+    SetPosition([f32; 3]),
+    SetGeometry(Geometry, bool),
+    SetDistanceModel(DistanceModel),
+    SetAirAbsorption(AirAbsorption),
+    SetReverbSend(f32),
+    SetOcclusion(f32),
+    SetObstruction(f32),
+    Fade(f32, Duration),
+    FadeOut(Duration),
     Stop,
     Pause,
     Resume,
 }
 
+/// Position and motion of a source, and how it radiates
+#[derive(Copy, Clone)]
+struct Geometry {
+    position: Option<[f32; 3]>,
+    velocity: [f32; 3],
+    direction: [f32; 3],
+    directivity: Directivity,
+    spread: Spread,
+    doppler_factor: f32,
+}
+
+impl Geometry {
+    fn relative_position(&self, listener: &ListenerState) -> Option<[f32; 3]> {
+        let [x, y, z] = listener.position;
+        self.position.map(|p| [p[0] - x, p[1] - y, p[2] - z])
+    }
+
+    /// compute weights of the direct sound and of the reverb send for the current position and
+    /// direction
+    fn weights(&self, listener: &ListenerState, model: &DistanceModel) -> (Bweights, Bweights) {
+        match self.relative_position(listener) {
+            Some(p) => {
+                let send = compute_weights(p, model, &self.spread);
+                let mut direct = send;
+                direct.amplify(self.directivity.gain(self.direction, p));
+                (direct, send)
+            }
+            None => (Bweights::omni_source(), Bweights::omni_source()),
+        }
+    }
+
+    /// compute doppler rate
+    fn doppler_rate(&self, listener: &ListenerState, speed_of_sound: f32) -> f32 {
+        compute_doppler_rate(
+            self.relative_position(listener).unwrap_or([0.0, 0.0, 0.0]),
+            self.velocity,
+            listener.velocity,
+            self.doppler_factor,
+            speed_of_sound,
+        )
+    }
+
+    /// compute the speed at which the source recedes, as seen by the doppler effect
+    fn receding_speed(&self, listener: &ListenerState, speed_of_sound: f32) -> f32 {
+        let (source_speed, _) = radial_speeds(
+            self.relative_position(listener).unwrap_or([0.0, 0.0, 0.0]),
+            self.velocity,
+            listener.velocity,
+            self.doppler_factor,
+            speed_of_sound,
+        );
+        source_speed
+    }
+}
+
+/// Geometry of a world-space source and the listener it is heard by
+struct World {
+    geometry: Geometry,
+    listener: ListenerState,
+}
+
 /// Bridges a Bstream and its controller across threads
 pub struct BstreamBridge {
-    commands: Mutex<Vec<Command>>,
-    pending_commands: AtomicBool,
     stopped: AtomicBool,
 }
 
+impl BstreamBridge {
+    pub(crate) fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
 /// Controls playback and position of a spatial audio source
 pub struct SoundController {
     bridge: Arc<BstreamBridge>,
-    position: [f32; 3],
-    velocity: [f32; 3],
-    doppler_factor: f32,
+    commands: Sender<Command>,
+    gain: f32,
+    muted: bool,
+    geometry: Geometry,
+    world_space: bool,
+    listener_velocity: [f32; 3],
     speed_of_sound: f32,
+    distance_model: DistanceModel,
 }
 
 impl SoundController {
-    /// Set source position relative to listener
+    /// Set source position relative to listener, or in world space
     ///
     /// Abruptly changing the position of a sound source may cause
     /// popping artifacts. Use this function only to set the source's
     /// initial position, and dynamically adjust the position with
     /// `adjust_position`.
     pub fn set_position(&mut self, pos: [f32; 3]) {
-        self.position = pos;
-        let weights = Bweights::from_position(pos);
-        let rate = self.doppler_rate();
-        {
-            let mut cmds = self.bridge.commands.lock().unwrap();
-            cmds.push(Command::SetSpeed(rate));
-            cmds.push(Command::SetWeights(weights));
-            cmds.push(Command::SetTarget(weights));
-        }
-        self.bridge.pending_commands.store(true, Ordering::SeqCst);
+        self.geometry.position = Some(pos);
+        self.update_geometry(true);
     }
-    /// Adjust source position relative to listener
+    /// Adjust source position relative to listener, or in world space
     ///
     /// The source transitions smoothly to the new position.
     /// Use this function to dynamically change the position of a
     /// sound source while it is playing.
     pub fn adjust_position(&mut self, pos: [f32; 3]) {
-        self.position = pos;
-        let weights = Bweights::from_position(pos);
-        let rate = self.doppler_rate();
-        {
-            let mut cmds = self.bridge.commands.lock().unwrap();
-            cmds.push(Command::SetSpeed(rate));
-            cmds.push(Command::SetTarget(weights));
-        }
-        self.bridge.pending_commands.store(true, Ordering::SeqCst);
+        self.geometry.position = Some(pos);
+        self.update_geometry(false);
     }
 
-    /// Set source velocity relative to listener
+    /// Set source velocity relative to listener, or in world space
     ///
     /// The velocity determines how much doppler effect to apply
     /// but has no effect on the source's position. Use
     /// `adjust_position` to update the source's position.
     pub fn set_velocity(&mut self, vel: [f32; 3]) {
-        self.velocity = vel;
-        let rate = self.doppler_rate();
-        self.send_command(Command::SetSpeed(rate));
+        self.geometry.velocity = vel;
+        self.update_geometry(false);
+    }
+
+    /// Set listener velocity, for the doppler effect of this source
+    ///
+    /// Has no effect on world-space sources, which take the velocity of the scene's `Listener`
+    /// instead.
+    pub fn set_listener_velocity(&mut self, vel: [f32; 3]) {
+        self.listener_velocity = vel;
+        if !self.world_space {
+            self.update_geometry(false);
+        }
     }
 
     /// Stop playback
+    ///
+    /// The source is cut off immediately. Use `stop_with_fade` to avoid an audible click.
     pub fn stop(&self) {
         self.send_command(Command::Stop);
     }
 
+    /// Fade out over `duration`, then stop playback
+    ///
+    /// A paused source stops immediately. Stopping cannot be undone: later gain changes and
+    /// unmuting have no effect on a source that is fading out.
+    pub fn stop_with_fade(&self, duration: Duration) {
+        self.send_command(Command::FadeOut(duration));
+    }
+
+    /// Set the source's gain
+    ///
+    /// The gain changes over a few milliseconds to avoid clicks. If the source is muted, the
+    /// new gain takes effect when it is unmuted.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.fade_to(gain, DECLICK_DURATION);
+    }
+
+    /// Linearly change the source's gain over `duration`
+    ///
+    /// If the source is muted, the new gain takes effect when it is unmuted.
+    pub fn fade_to(&mut self, gain: f32, duration: Duration) {
+        self.gain = gain;
+        if !self.muted {
+            self.send_command(Command::Fade(gain, duration));
+        }
+    }
+
+    /// Current gain of the source, as last set on this controller
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Silence the source without pausing it
+    pub fn mute(&mut self) {
+        self.muted = true;
+        self.send_command(Command::Fade(0.0, DECLICK_DURATION));
+    }
+
+    /// Restore the source's gain after muting it
+    pub fn unmute(&mut self) {
+        self.muted = false;
+        self.send_command(Command::Fade(self.gain, DECLICK_DURATION));
+    }
+
+    /// Wether or not the source is muted
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
     /// Pause playback
     pub fn pause(&self) {
         self.send_command(Command::Pause);
@@ -292,68 +1068,227 @@ impl SoundController {
 
     /// Set doppler factor
     pub fn set_doppler_factor(&mut self, factor: f32) {
-        self.doppler_factor = factor;
+        self.geometry.doppler_factor = factor;
+        self.update_geometry(false);
     }
 
-	/// Wether or not the sound has stopped.
-	pub fn stopped(&self) -> bool {
-		self.bridge.stopped.load(Ordering::SeqCst)
-	}
+    /// Set how the source is attenuated with distance
+    ///
+    /// The source's level transitions smoothly to the new attenuation. Has no effect on
+    /// omnidirectional sources that have never been positioned.
+    pub fn set_distance_model(&mut self, model: DistanceModel) {
+        self.distance_model = model;
+        self.send_command(Command::SetDistanceModel(self.distance_model.clone()));
+        self.update_geometry(false);
+    }
+
+    /// Set the direction the source faces, relative to the listener's orientation, or in world
+    /// space
+    ///
+    /// The source's level transitions smoothly to the new direction. Has no effect on
+    /// omnidirectional sources that have never been positioned.
+    pub fn set_direction(&mut self, direction: [f32; 3]) {
+        self.geometry.direction = direction;
+        self.update_geometry(false);
+    }
+
+    /// Set the pattern with which the source radiates around its direction
+    ///
+    /// The source's level transitions smoothly to the new pattern. Has no effect on
+    /// omnidirectional sources that have never been positioned.
+    pub fn set_directivity(&mut self, directivity: Directivity) {
+        self.geometry.directivity = directivity;
+        self.update_geometry(false);
+    }
+
+    /// Set the spatial extent of the source
+    ///
+    /// The source transitions smoothly to the new extent, so the spread can be animated by
+    /// calling this function repeatedly. Has no effect on omnidirectional sources that have
+    /// never been positioned.
+    pub fn set_spread(&mut self, spread: Spread) {
+        self.geometry.spread = spread;
+        self.update_geometry(false);
+    }
+
+    /// Set how the source is filtered by the air with distance
+    ///
+    /// The filter transitions smoothly to the new cutoff frequency.
+    pub fn set_air_absorption(&self, model: AirAbsorption) {
+        self.send_command(Command::SetAirAbsorption(model));
+    }
+
+    /// Set how much of the source is sent to the scene's reverb
+    ///
+    /// The level changes over a few milliseconds to avoid clicks. Has no effect on sound fields.
+    pub fn set_reverb_send(&self, level: f32) {
+        self.send_command(Command::SetReverbSend(level));
+    }
+
+    /// Set how much the source is occluded, from 0 (not at all) to 1 (fully)
+    ///
+    /// Occlusion muffles the direct sound, reflections, and reverb send, like a source in
+    /// another room. The source transitions smoothly to the new amount. Has no effect on sound
+    /// fields.
+    pub fn set_occlusion(&self, amount: f32) {
+        self.send_command(Command::SetOcclusion(amount));
+    }
+
+    /// Set how much the source is obstructed, from 0 (not at all) to 1 (fully)
+    ///
+    /// Obstruction muffles only the direct sound, like a source behind a pillar in the same
+    /// room; reflections and reverb send are not affected. The source transitions smoothly to
+    /// the new amount. Has no effect on sound fields.
+    pub fn set_obstruction(&self, amount: f32) {
+        self.send_command(Command::SetObstruction(amount));
+    }
+
+    /// Wether or not the sound has stopped.
+    pub fn stopped(&self) -> bool {
+        self.bridge.stopped()
+    }
+
+    pub(crate) fn bridge(&self) -> &Arc<BstreamBridge> {
+        &self.bridge
+    }
 
     fn send_command(&self, cmd: Command) {
-        self.bridge.commands.lock().unwrap().push(cmd);
-        self.bridge.pending_commands.store(true, Ordering::SeqCst);
+        self.commands.send(cmd);
     }
 
-    /// compute doppler rate
-    fn doppler_rate(&self) -> f32 {
-        compute_doppler_rate(
-            self.position,
-            self.velocity,
-            self.doppler_factor,
-            self.speed_of_sound,
-        )
+    /// Send the source's geometry to the stream
+    ///
+    /// World-space sources compute their weights and doppler rate on the audio thread, where
+    /// the listener is known. If `jump` is true, the source does not transition smoothly.
+    fn update_geometry(&self, jump: bool) {
+        if self.world_space {
+            self.send_command(Command::SetGeometry(self.geometry, jump));
+            return;
+        }
+
+        let listener = ListenerState {
+            position: [0.0, 0.0, 0.0],
+            velocity: self.listener_velocity,
+        };
+        let (weights, send_weights) = self.geometry.weights(&listener, &self.distance_model);
+        let rate = self.geometry.doppler_rate(&listener, self.speed_of_sound);
+        let receding_speed = self.geometry.receding_speed(&listener, self.speed_of_sound);
+        self.send_command(Command::SetSpeed(rate, receding_speed));
+        if jump {
+            self.send_command(Command::SetWeights(weights, send_weights));
+        }
+        self.send_command(Command::SetTarget(weights, send_weights));
+        if let Some(pos) = self.geometry.position {
+            self.send_command(Command::SetPosition(pos));
+        }
     }
 }
 
+/// compute weights of an omnidirectional source at given position
+fn compute_weights(position: [f32; 3], model: &DistanceModel, spread: &Spread) -> Bweights {
+    let mut weights = Bweights::from_position(position, model);
+    spread.apply(&mut weights, norm(position));
+    weights
+}
+
 /// compute doppler rate
+///
+/// `position` is relative to the listener; both velocities are relative to the medium. The rate
+/// is limited to `1 / MAX_DOPPLER_RATE..=MAX_DOPPLER_RATE`.
 fn compute_doppler_rate(
     position: [f32; 3],
     velocity: [f32; 3],
+    listener_velocity: [f32; 3],
     doppler_factor: f32,
     speed_of_sound: f32,
 ) -> f32 {
-    let dist =
-        (position[0] * position[0] + position[1] * position[1] + position[2] * position[2]).sqrt();
+    let (source_speed, listener_speed) = radial_speeds(
+        position,
+        velocity,
+        listener_velocity,
+        doppler_factor,
+        speed_of_sound,
+    );
+    let rate = (speed_of_sound + listener_speed) / (speed_of_sound + source_speed);
+    rate.clamp(1.0 / MAX_DOPPLER_RATE, MAX_DOPPLER_RATE)
+}
+
+/// compute the speeds of the source away from the listener and of the listener towards the
+/// source, scaled by the doppler factor
+///
+/// Speeds that would close the gap between source and sound wave are limited to a fraction of
+/// the speed of sound, which keeps the doppler rate finite and positive for supersonic sources
+/// and listeners.
+fn radial_speeds(
+    position: [f32; 3],
+    velocity: [f32; 3],
+    listener_velocity: [f32; 3],
+    doppler_factor: f32,
+    speed_of_sound: f32,
+) -> (f32, f32) {
+    let dist = norm(position);
 
-    let relative_velocity;
+    let (source_speed, listener_speed) = if dist.abs() < EPS {
+        (norm(velocity), 0.0)
+    } else {
+        let along =
+            |v: [f32; 3]| (position[0] * v[0] + position[1] * v[1] + position[2] * v[2]) / dist;
+        (along(velocity), along(listener_velocity))
+    };
+
+    // approaching sources and receding listeners are limited to subsonic speeds
+    let limit = -MAX_DOPPLER_MACH * speed_of_sound;
+    let source_speed = (doppler_factor * source_speed).max(limit);
+    let listener_speed = (doppler_factor * listener_speed).max(limit);
+    (source_speed, listener_speed)
+}
 
-    if dist.abs() < EPS {
-        relative_velocity =
-            (velocity[0] * velocity[0] + velocity[1] * velocity[1] + velocity[2] * velocity[2])
-                .sqrt();
+/// Change of the propagation delay per sample that corresponds to a doppler rate
+///
+/// The delay line reads `1 - slope` input samples per output sample, which is the rate.
+fn delay_slope(doppler_rate: f32) -> f32 {
+    if doppler_rate.is_finite() {
+        1.0 - doppler_rate
     } else {
-        relative_velocity =
-            (position[0] * velocity[0] + position[1] * velocity[1] + position[2] * velocity[2])
-                / dist;
+        0.0
     }
+}
 
-    speed_of_sound / (speed_of_sound + doppler_factor * relative_velocity)
+/// Propagation delay in samples of the sound that reaches the listener now
+///
+/// The sound left the source when it was closer by the distance it has receded since, so the
+/// delay is `distance / (c + receding_speed)` rather than `distance / c`.
+fn propagation_delay(
+    distance: f32,
+    receding_speed: f32,
+    speed_of_sound: f32,
+    sample_rate: u32,
+) -> f32 {
+    distance / (speed_of_sound + receding_speed) * sample_rate as f32
 }
 
 const EPS: f32 = 1e-6;
 
+/// Maximum speed of a source towards the listener, or of the listener away from a source,
+/// relative to the speed of sound, for the doppler effect
+const MAX_DOPPLER_MACH: f32 = 0.95;
+
+/// Maximum factor by which the doppler effect raises or lowers the pitch
+const MAX_DOPPLER_RATE: f32 = 20.0;
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::sources::Ramp;
+    use crate::sources::{Constant, Ramp};
+    use rodio::buffer::SamplesBuffer;
+    use std::f32::consts::FRAC_1_SQRT_2;
 
     #[test]
     fn no_doppler_effect_if_velocity_is_zero() {
         let position = [0.0, 1.0, 0.0];
         let velocity = [0.0, 0.0, 0.0];
 
-        let rate = compute_doppler_rate(position, velocity, 1.0, 1.0);
+        let rate = compute_doppler_rate(position, velocity, [0.0; 3], 1.0, 1.0);
 
         assert_eq!(rate, 1.0);
     }
@@ -363,11 +1298,40 @@ mod tests {
         let position = [0.0, 0.0, 0.0];
         let velocity = [1.0, 1.0, 1.0];
 
-        let rate = compute_doppler_rate(position, velocity, 1.0, 1.0);
+        let rate = compute_doppler_rate(position, velocity, [0.0; 3], 1.0, 1.0);
 
         assert_eq!(rate, 1.0 / (1.0 + f32::sqrt(3.0)));
     }
 
+    #[test]
+    fn listeners_moving_towards_sources_hear_higher_pitch() {
+        let position = [0.0, 10.0, 0.0];
+
+        let approaching = compute_doppler_rate(position, [0.0; 3], [0.0, 0.5, 0.0], 1.0, 1.0);
+        assert_eq!(approaching, 1.5);
+
+        let receding = compute_doppler_rate(position, [0.0; 3], [0.0, -0.5, 0.0], 1.0, 1.0);
+        assert_eq!(receding, 0.5);
+
+        // moving along with the source cancels the effect
+        let rate = compute_doppler_rate(position, [0.0, 0.5, 0.0], [0.0, 0.5, 0.0], 1.0, 1.0);
+        assert_eq!(rate, 1.0);
+    }
+
+    #[test]
+    fn supersonic_doppler_rates_stay_finite_and_positive() {
+        let position = [0.0, 10.0, 0.0];
+        for speed in [-1.0, -2.0, -100.0, -1e6, 1.0, 100.0, 1e6] {
+            let source = compute_doppler_rate(position, [0.0, speed, 0.0], [0.0; 3], 1.0, 1.0);
+            let listener = compute_doppler_rate(position, [0.0; 3], [0.0, speed, 0.0], 1.0, 1.0);
+            for rate in [source, listener] {
+                assert!(rate.is_finite() && rate > 0.0, "{} at {}", rate, speed);
+                assert!(rate >= 1.0 / MAX_DOPPLER_RATE, "{} at {}", rate, speed);
+                assert!(rate <= MAX_DOPPLER_RATE, "{} at {}", rate, speed);
+            }
+        }
+    }
+
     #[test]
     fn sources_start_playing_with_first_sample() {
         let (mut stream, _) = bstream(
@@ -383,6 +1347,21 @@ mod tests {
         assert_eq!(stream.next(), Some(3.0));
     }
 
+    #[test]
+    fn streams_can_be_played_as_interleaved_sources() {
+        let (stream, _) = bstream(
+            Constant::new(1.0, 1000),
+            BstreamConfig::new().with_position([1.0, 0.0, 0.0]),
+        );
+
+        let source = stream.into_source(AmbisonicOrder::First);
+        assert_eq!(source.channels(), 4);
+        assert_eq!(source.sample_rate(), 1000);
+
+        let frame: Vec<f32> = source.take(4).collect();
+        assert_eq!(frame, [FRAC_1_SQRT_2, 1.0, 0.0, 0.0]);
+    }
+
     #[test]
     fn pausing_a_source_makes_it_emit_zeros() {
         let (mut stream, controller) = bstream(
@@ -424,6 +1403,312 @@ mod tests {
         assert_eq!(stream.next(), Some(3.0));
     }
 
+    #[test]
+    fn distance_model_attenuates_sources() {
+        let model = DistanceModel::linear().with_max_distance(3.0);
+        let (mut stream, _) = bstream(
+            Constant::new(1.0, 1),
+            BstreamConfig::new()
+                .with_position([2.0, 0.0, 0.0])
+                .with_distance_model(model),
+        );
+
+        let mut stream = extract_x_component(&mut stream);
+
+        assert_eq!(stream.next(), Some(0.5));
+    }
+
+    #[test]
+    fn sound_fields_are_resampled_and_can_be_stopped() {
+        let frames = Ramp::new(2).map(|x| Bweights::new(0.0, 1.0, 0.0, 0.0).scale(x));
+        let source = BformatSource::new(frames, 2, AmbisonicOrder::First);
+
+        let (mut field, controller) = bfield(source, BstreamConfig::new(), 1);
+
+        {
+            let mut field = extract_x_component(&mut field);
+            assert_eq!(field.next(), Some(0.0));
+            assert_eq!(field.next(), Some(1.0));
+            assert_eq!(field.next(), Some(2.0));
+        }
+
+        controller.stop();
+        assert!(field.next().is_none());
+        assert!(controller.stopped());
+    }
+
+    #[test]
+    fn gain_fades_linearly() {
+        let (mut stream, mut controller) = bstream(
+            Constant::new(1.0, 1),
+            BstreamConfig::new()
+                .with_position([1.0, 0.0, 0.0])
+                .with_gain(0.0),
+        );
+
+        controller.fade_to(1.0, Duration::from_secs(4));
+
+        let stream = extract_x_component(&mut stream);
+        let samples: Vec<f32> = stream.take(6).collect();
+        assert_eq!(samples, [0.0, 0.25, 0.5, 0.75, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn muting_keeps_the_gain() {
+        let (mut stream, mut controller) = bstream(
+            Constant::new(1.0, 1000),
+            BstreamConfig::new().with_position([1.0, 0.0, 0.0]),
+        );
+
+        controller.set_gain(0.5);
+        controller.mute();
+        assert_eq!(extract_x_component(&mut stream).nth(10), Some(0.0));
+
+        controller.set_gain(0.25);
+        assert_eq!(extract_x_component(&mut stream).nth(10), Some(0.0));
+
+        controller.unmute();
+        assert_eq!(extract_x_component(&mut stream).nth(10), Some(0.25));
+        assert!(!controller.muted());
+    }
+
+    #[test]
+    fn sources_stop_after_fading_out() {
+        let (mut stream, controller) = bstream(
+            Constant::new(1.0, 1),
+            BstreamConfig::new().with_position([1.0, 0.0, 0.0]),
+        );
+
+        controller.stop_with_fade(Duration::from_secs(2));
+
+        let samples: Vec<f32> = extract_x_component(&mut stream).collect();
+        assert_eq!(samples, [1.0, 0.5]);
+        assert!(controller.stopped());
+    }
+
+    #[test]
+    fn fading_out_cannot_be_undone() {
+        let (mut stream, mut controller) = bstream(
+            Constant::new(1.0, 1),
+            BstreamConfig::new().with_position([1.0, 0.0, 0.0]),
+        );
+
+        controller.stop_with_fade(Duration::from_secs(2));
+        controller.set_gain(1.0);
+
+        let samples: Vec<f32> = extract_x_component(&mut stream).collect();
+        assert_eq!(samples, [1.0, 0.5]);
+    }
+
+    #[test]
+    fn paused_sources_stop_without_fading() {
+        let (mut stream, controller) = bstream(
+            Constant::new(1.0, 1),
+            BstreamConfig::new().with_position([1.0, 0.0, 0.0]),
+        );
+
+        controller.pause();
+        controller.stop_with_fade(Duration::from_secs(2));
+
+        assert_eq!(stream.next(), None);
+        assert!(controller.stopped());
+    }
+
+    #[test]
+    fn air_absorption_dulls_distant_sources() {
+        let peak = |absorption: AirAbsorption| {
+            let (mut stream, _) = bstream(
+                rodio::source::SineWave::new(12000.0),
+                BstreamConfig::new()
+                    .with_position([1000.0, 0.0, 0.0])
+                    .with_distance_model(DistanceModel::none())
+                    .with_air_absorption(absorption),
+            );
+            extract_x_component(&mut stream)
+                .skip(1000)
+                .take(100)
+                .fold(0.0f32, |m, x| m.max(x.abs()))
+        };
+
+        assert!(peak(AirAbsorption::none()) > 0.9);
+        assert!(peak(AirAbsorption::standard()) < 0.01);
+    }
+
+    #[test]
+    fn propagation_delay_postpones_sources_until_they_arrive() {
+        let (mut stream, _) = bstream(
+            SamplesBuffer::new(1, 1, vec![1.0; 3]),
+            BstreamConfig::new()
+                .with_position([2.0, 0.0, 0.0])
+                .with_distance_model(DistanceModel::none())
+                .with_speed_of_sound(1.0)
+                .with_propagation_delay(true),
+        );
+
+        let samples: Vec<f32> = extract_x_component(&mut stream).collect();
+        assert_eq!(samples[..5], [0.0, 0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(samples.iter().sum::<f32>(), 3.0);
+    }
+
+    #[test]
+    fn propagation_delay_is_limited() {
+        let (mut stream, _) = bstream(
+            SamplesBuffer::new(1, 1, vec![1.0; 3]),
+            BstreamConfig::new()
+                .with_position([5.0, 0.0, 0.0])
+                .with_distance_model(DistanceModel::none())
+                .with_speed_of_sound(1.0)
+                .with_propagation_delay(true)
+                .with_max_propagation_delay(Duration::from_secs(2)),
+        );
+
+        let samples: Vec<f32> = extract_x_component(&mut stream).collect();
+        assert_eq!(samples[..5], [0.0, 0.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn propagation_delay_shifts_the_pitch_like_resampling() {
+        let sample_rate = 1000;
+        let sine: Vec<f32> = (0..4000)
+            .map(|i| (2.0 * std::f32::consts::PI * 50.0 * i as f32 / sample_rate as f32).sin())
+            .collect();
+
+        // count upward zero crossings once the delayed sound has arrived
+        let crossings = |propagation_delay| {
+            let (mut stream, _) = bstream(
+                SamplesBuffer::new(1, sample_rate, sine.clone()),
+                BstreamConfig::new()
+                    .with_position([10.0, 0.0, 0.0])
+                    .with_velocity([25.0, 0.0, 0.0])
+                    .with_distance_model(DistanceModel::none())
+                    .with_speed_of_sound(100.0)
+                    .with_propagation_delay(propagation_delay),
+            );
+            let samples: Vec<f32> = extract_x_component(&mut stream)
+                .skip(200)
+                .take(2000)
+                .collect();
+            samples
+                .windows(2)
+                .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+                .count()
+        };
+
+        // a source receding at a quarter of the speed of sound is heard at 4/5 of its pitch
+        let resampled = crossings(false);
+        let delayed = crossings(true);
+        assert!((resampled as i32 - 80).abs() <= 1);
+        assert!((delayed as i32 - resampled as i32).abs() <= 1);
+    }
+
+    #[test]
+    fn all_interpolations_start_with_first_sample() {
+        for interpolation in [
+            Interpolation::Linear,
+            Interpolation::Cubic,
+            Interpolation::Sinc,
+        ] {
+            let (mut stream, _) = bstream(
+                Ramp::new(1),
+                BstreamConfig::new()
+                    .with_position([1.0, 0.0, 0.0])
+                    .with_interpolation(interpolation),
+            );
+
+            // the sinc filter slightly blurs the samples
+            let mut stream = extract_x_component(&mut stream);
+            assert!(stream.next().unwrap().abs() < 0.05);
+            assert!((stream.next().unwrap() - 1.0).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn directional_sources_are_quieter_behind() {
+        let (mut stream, mut controller) = bstream(
+            Constant::new(1.0, 1000),
+            BstreamConfig::new()
+                .with_position([1.0, 0.0, 0.0])
+                .with_direction([-1.0, 0.0, 0.0])
+                .with_directivity(Directivity::cone(60.0, 120.0, 0.25)),
+        );
+        assert_eq!(extract_x_component(&mut stream).next(), Some(1.0));
+
+        controller.set_direction([1.0, 0.0, 0.0]);
+        let level = extract_x_component(&mut stream).nth(10000).unwrap();
+        assert!((level - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn directivity_does_not_affect_the_reverb_send() {
+        let send = |direction| {
+            let (mut stream, _) = bstream(
+                Constant::new(1.0, 1000),
+                BstreamConfig::new()
+                    .with_position([1.0, 0.0, 0.0])
+                    .with_direction(direction)
+                    .with_directivity(Directivity::cone(60.0, 120.0, 0.25)),
+            );
+            let direct = stream.nth(100).unwrap();
+            (direct.x(), stream.reverb_send().x())
+        };
+
+        let (facing, facing_send) = send([-1.0, 0.0, 0.0]);
+        let (away, away_send) = send([1.0, 0.0, 0.0]);
+        assert!((away - 0.25 * facing).abs() < 1e-6);
+        assert_eq!(away_send, facing_send);
+        assert_eq!(facing_send, facing);
+    }
+
+    #[test]
+    fn spread_sources_lose_their_direction() {
+        let (mut stream, mut controller) = bstream(
+            Constant::new(1.0, 1000),
+            BstreamConfig::new()
+                .with_position([1.0, 0.0, 0.0])
+                .with_spread(Spread::angle(360.0)),
+        );
+        let frame = stream.next().unwrap();
+        assert_eq!(frame.w(), Bweights::omni_source().scale(1.0).w());
+        assert!(frame.components()[1..].iter().all(|x| x.abs() < 1e-6));
+
+        controller.set_spread(Spread::point());
+        assert!((extract_x_component(&mut stream).nth(10000).unwrap() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn near_field_boosts_close_sources() {
+        let (mut stream, _) = bstream(
+            Constant::new(1.0, 48000),
+            BstreamConfig::new()
+                .with_position([0.5, 0.0, 0.0])
+                .with_near_field(1.0),
+        );
+
+        // the omnidirectional component is unchanged, and the first order is doubled
+        let frame = stream.nth(4800).unwrap();
+        assert_eq!(frame.w(), Bweights::omni_source().scale(1.0).w());
+        assert!((frame.components()[1] - 2.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn obstruction_leaves_the_reverb_send_intact() {
+        let (mut stream, controller) = bstream(
+            Constant::new(1.0, 48000),
+            BstreamConfig::new().with_position([1.0, 0.0, 0.0]),
+        );
+
+        controller.set_obstruction(1.0);
+        let output = stream.nth(48000).unwrap();
+        assert!((output.w() / FRAC_1_SQRT_2 - 0.1).abs() < 1e-3);
+        assert!((stream.reverb_send().w() / FRAC_1_SQRT_2 - 1.0).abs() < 1e-6);
+
+        controller.set_obstruction(0.0);
+        controller.set_occlusion(1.0);
+        let output = stream.nth(48000).unwrap();
+        assert!((output.w() / FRAC_1_SQRT_2 - 0.1).abs() < 1e-3);
+        assert!((stream.reverb_send().w() / FRAC_1_SQRT_2 - 0.1).abs() < 1e-3);
+    }
+
     fn extract_x_component(stream: impl Iterator<Item = Bformat>) -> impl Iterator<Item = f32> {
         stream.map(|bsample| Bweights::new(0.0, 1.0, 0.0, 0.0).dot(bsample))
     }
@@ -0,0 +1,482 @@
+//! Group sources into buses
+//!
+//! Every source is mixed into a bus, which applies a common gain and effect chain. Sources that
+//! are not assigned to a named bus play on the master bus, which also receives the output of all
+//! named buses.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::bformat::Bformat;
+use crate::bstream::{Bfield, Bstream, Fader, DECLICK_DURATION};
+use crate::listener::ListenerState;
+use crate::queue::{self, Receiver, Sender};
+use crate::reverb::Reverb;
+use crate::room::{EarlyReflections, Room};
+
+/// Process the *B-format* mix of a bus, sample by sample.
+///
+/// Closures of type `FnMut(Bformat) -> Bformat` implement this trait.
+pub trait Effect {
+    /// Process one *B-format* sample.
+    fn process(&mut self, input: Bformat) -> Bformat;
+}
+
+impl<F: FnMut(Bformat) -> Bformat> Effect for F {
+    fn process(&mut self, input: Bformat) -> Bformat {
+        self(input)
+    }
+}
+
+enum Command {
+    Fade(f32, Duration),
+    Mute(bool),
+    Pause,
+    Resume,
+    AddEffect(Box<dyn Effect + Send>),
+    /// Larger storage for the effect chain
+    ReserveEffects(Vec<Box<dyn Effect + Send>>),
+    ClearEffects,
+}
+
+/// Number of commands that can be pending for a bus before the queue overflows
+const COMMAND_CAPACITY: usize = 16;
+
+/// Number of sources, sound fields, and effects for which a bus initially reserves room
+///
+/// The control side sends larger storage before they run out, so that adding them does not
+/// allocate on the audio thread.
+pub(crate) const STREAM_CAPACITY: usize = 64;
+pub(crate) const FIELD_CAPACITY: usize = 8;
+const EFFECT_CAPACITY: usize = 8;
+
+/// Room that the audio thread has reserved in a `Vec`, as tracked by the control side
+pub(crate) struct Capacity(usize);
+
+impl Capacity {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Capacity(capacity)
+    }
+
+    /// Storage for the audio thread if `len` elements would not fit into the reserved room
+    ///
+    /// The room at least doubles, so that growing is rare.
+    pub(crate) fn grow<T>(&mut self, len: usize) -> Option<Vec<T>> {
+        if len <= self.0 {
+            return None;
+        }
+        self.0 = len.max(2 * self.0);
+        Some(Vec::with_capacity(self.0))
+    }
+}
+
+/// Move the elements of `vec` into the larger `storage`, and return the old storage
+pub(crate) fn regrow<T>(vec: &mut Vec<T>, mut storage: Vec<T>) -> Vec<T> {
+    storage.append(vec);
+    std::mem::replace(vec, storage)
+}
+
+/// Sources and effects that the audio thread has finished with
+///
+/// They are handed back to the control side, so that they are not freed on the audio thread.
+/// The values are only held to be dropped there. Sources are not boxed, because the audio
+/// thread would free the boxes.
+#[allow(clippy::large_enum_variant, dead_code)]
+pub(crate) enum Released {
+    Stream(Bstream),
+    Field(Bfield),
+    Reverb(Reverb),
+    Effect(Box<dyn Effect + Send>),
+    Reflections(Vec<Option<EarlyReflections>>),
+    /// Storage that has been replaced by larger storage
+    Streams(Vec<Bstream>),
+    Fields(Vec<Bfield>),
+    Effects(Vec<Box<dyn Effect + Send>>),
+    Buses(Vec<Bus>),
+}
+
+/// Hand a value back to the control side
+///
+/// If the control side has not collected released values for a long time and the queue is
+/// full, the value is dropped here after all.
+pub(crate) fn release(released: &Sender<Released>, value: Released) {
+    if let Err(value) = released.try_send(value) {
+        drop(value);
+    }
+}
+
+/// Controls the gain, playback, and effects of a bus
+///
+/// Controllers can be cloned; all clones control the same bus.
+#[derive(Clone)]
+pub struct BusController {
+    commands: Sender<Command>,
+    effects: Arc<Mutex<EffectCount>>,
+}
+
+/// Number of effects on a bus, and the room the bus has reserved for them
+struct EffectCount {
+    len: usize,
+    capacity: Capacity,
+}
+
+impl BusController {
+    /// Set the bus gain
+    ///
+    /// The gain changes over a few milliseconds to avoid clicks.
+    pub fn set_gain(&self, gain: f32) {
+        self.fade_to(gain, DECLICK_DURATION);
+    }
+
+    /// Linearly change the bus gain over `duration`
+    pub fn fade_to(&self, gain: f32, duration: Duration) {
+        self.send_command(Command::Fade(gain, duration));
+    }
+
+    /// Silence the bus without pausing its sources
+    pub fn mute(&self) {
+        self.send_command(Command::Mute(true));
+    }
+
+    /// Restore the bus gain after muting it
+    pub fn unmute(&self) {
+        self.send_command(Command::Mute(false));
+    }
+
+    /// Pause all sources on the bus
+    ///
+    /// The bus fades out over a few milliseconds to avoid clicks. Then its sources do not
+    /// advance until the bus is resumed, which fades it back in.
+    pub fn pause(&self) {
+        self.send_command(Command::Pause);
+    }
+
+    /// Resume all sources on the bus
+    pub fn resume(&self) {
+        self.send_command(Command::Resume);
+    }
+
+    /// Append an effect to the end of the bus's effect chain
+    pub fn add_effect<E: Effect + Send + 'static>(&self, effect: E) {
+        let mut effects = self.effects.lock().expect("Cannot lock effects");
+        effects.len += 1;
+        let len = effects.len;
+        if let Some(storage) = effects.capacity.grow(len) {
+            self.send_command(Command::ReserveEffects(storage));
+        }
+        self.send_command(Command::AddEffect(Box::new(effect)));
+    }
+
+    /// Remove all effects from the bus
+    pub fn clear_effects(&self) {
+        let mut effects = self.effects.lock().expect("Cannot lock effects");
+        effects.len = 0;
+        self.send_command(Command::ClearEffects);
+    }
+
+    fn send_command(&self, cmd: Command) {
+        self.commands.send(cmd);
+    }
+}
+
+/// Mix of a group of sources
+pub(crate) struct Bus {
+    commands: Receiver<Command>,
+    released: Sender<Released>,
+    streams: Vec<Bstream>,
+    fields: Vec<Bfield>,
+    effects: Vec<Box<dyn Effect + Send>>,
+    reverb: Option<Reverb>,
+    gain: Fader,
+    /// Fades the bus out when it is muted or about to pause
+    mute: Fader,
+    muted: bool,
+    pausing: bool,
+    paused: bool,
+}
+
+impl Bus {
+    /// Construct a bus and its controller
+    ///
+    /// Sources that end and effects that are removed are sent to `released`.
+    pub(crate) fn new(sample_rate: u32, released: Sender<Released>) -> (Self, BusController) {
+        let (sender, receiver) = queue::channel(COMMAND_CAPACITY);
+
+        let bus = Bus {
+            commands: receiver,
+            released,
+            streams: Vec::with_capacity(STREAM_CAPACITY),
+            fields: Vec::with_capacity(FIELD_CAPACITY),
+            effects: Vec::with_capacity(EFFECT_CAPACITY),
+            reverb: None,
+            gain: Fader::new(1.0, sample_rate),
+            mute: Fader::new(1.0, sample_rate),
+            muted: false,
+            pausing: false,
+            paused: false,
+        };
+
+        let controller = BusController {
+            commands: sender,
+            effects: Arc::new(Mutex::new(EffectCount {
+                len: 0,
+                capacity: Capacity::new(EFFECT_CAPACITY),
+            })),
+        };
+
+        (bus, controller)
+    }
+
+    pub(crate) fn add_stream(&mut self, stream: Bstream) {
+        self.streams.push(stream);
+    }
+
+    pub(crate) fn add_field(&mut self, field: Bfield) {
+        self.fields.push(field);
+    }
+
+    /// Move the sources into larger storage
+    pub(crate) fn reserve_streams(&mut self, storage: Vec<Bstream>) {
+        let old = regrow(&mut self.streams, storage);
+        release(&self.released, Released::Streams(old));
+    }
+
+    /// Move the sound fields into larger storage
+    pub(crate) fn reserve_fields(&mut self, storage: Vec<Bfield>) {
+        let old = regrow(&mut self.fields, storage);
+        release(&self.released, Released::Fields(old));
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Set the room that reflects the bus's sources
+    ///
+    /// The sources exchange their reflections for those in `pool`.
+    pub(crate) fn set_room(&mut self, room: Option<&Room>, pool: &mut [Option<EarlyReflections>]) {
+        for stream in &mut self.streams {
+            stream.exchange_reflections(room, pool);
+        }
+    }
+
+    /// Move the listener of the bus's world-space sources
+    pub(crate) fn set_listener(&mut self, listener: &ListenerState) {
+        for stream in &mut self.streams {
+            stream.set_listener(listener);
+        }
+    }
+
+    /// Set the reverb that processes the sources' reverb sends
+    ///
+    /// Without a reverb, the bus passes the sends on.
+    pub(crate) fn set_reverb(&mut self, reverb: Option<Reverb>) {
+        if let Some(old) = std::mem::replace(&mut self.reverb, reverb) {
+            release(&self.released, Released::Reverb(old));
+        }
+    }
+
+    /// Fade out while muted or pausing, and back in otherwise
+    fn update_mute(&mut self) {
+        let target = if self.muted || self.pausing { 0.0 } else { 1.0 };
+        self.mute.fade_to(target, DECLICK_DURATION);
+    }
+
+    /// Mix the next sample of all sources on the bus, together with `input`
+    ///
+    /// `send` is added to the sources' reverb sends. Returns the mix and the sends that have not
+    /// been processed by a reverb, both scaled by the bus gain. Sources that have ended are
+    /// removed and released.
+    pub(crate) fn next_frame(&mut self, input: Bformat, send: Bformat) -> (Bformat, Bformat) {
+        while let Some(cmd) = self.commands.recv() {
+            match cmd {
+                Command::Fade(gain, duration) => self.gain.fade_to(gain, duration),
+                Command::Mute(muted) => {
+                    self.muted = muted;
+                    self.update_mute();
+                }
+                Command::Pause => {
+                    self.pausing = true;
+                    self.update_mute();
+                }
+                Command::Resume => {
+                    self.pausing = false;
+                    self.paused = false;
+                    self.update_mute();
+                }
+                Command::AddEffect(effect) => self.effects.push(effect),
+                Command::ReserveEffects(storage) => {
+                    let old = regrow(&mut self.effects, storage);
+                    release(&self.released, Released::Effects(old));
+                }
+                Command::ClearEffects => {
+                    for effect in self.effects.drain(..) {
+                        release(&self.released, Released::Effect(effect));
+                    }
+                }
+            }
+        }
+
+        // the sources stop advancing once the bus has faded out
+        if self.pausing && self.mute.is_silent() {
+            self.paused = true;
+        }
+
+        if self.paused {
+            return (Bformat::zero(), Bformat::zero());
+        }
+
+        let mut mix = input;
+        let mut send = send;
+
+        let mut i = 0;
+        while i < self.streams.len() {
+            match self.streams[i].next() {
+                Some(x) => {
+                    mix += x;
+                    send += self.streams[i].reverb_send();
+                    i += 1;
+                }
+                None => release(
+                    &self.released,
+                    Released::Stream(self.streams.swap_remove(i)),
+                ),
+            }
+        }
+
+        let mut i = 0;
+        while i < self.fields.len() {
+            match self.fields[i].next() {
+                Some(x) => {
+                    mix += x;
+                    i += 1;
+                }
+                None => release(&self.released, Released::Field(self.fields.swap_remove(i))),
+            }
+        }
+
+        if let Some(reverb) = &mut self.reverb {
+            mix += reverb.process(send);
+            send = Bformat::zero();
+        }
+
+        for effect in &mut self.effects {
+            mix = effect.process(mix);
+        }
+
+        // faders never stop, so they always produce a gain
+        let gain = self.gain.next().unwrap_or(0.0) * self.mute.next().unwrap_or(0.0);
+        (mix * gain, send * gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bstream::{bstream, BstreamConfig};
+    use crate::sources::Constant;
+    use rodio::buffer::SamplesBuffer;
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    /// W component of the bus output, relative to that of a unit omnidirectional source
+    fn level(bus: &mut Bus, input: Bformat) -> f32 {
+        bus.next_frame(input, Bformat::zero()).0.w() / FRAC_1_SQRT_2
+    }
+
+    fn bus_with_source() -> (Bus, BusController) {
+        let (released, _) = queue::channel(16);
+        let (mut bus, controller) = Bus::new(1000, released);
+        let (stream, _) = bstream(Constant::new(1.0, 1000), BstreamConfig::new());
+        bus.add_stream(stream);
+        (bus, controller)
+    }
+
+    #[test]
+    fn buses_apply_gain_and_mute() {
+        let (mut bus, controller) = bus_with_source();
+        assert_eq!(level(&mut bus, Bformat::zero()), 1.0);
+
+        controller.set_gain(0.5);
+        controller.mute();
+        for _ in 0..10 {
+            bus.next_frame(Bformat::zero(), Bformat::zero());
+        }
+        assert_eq!(level(&mut bus, Bformat::zero()), 0.0);
+
+        controller.unmute();
+        for _ in 0..10 {
+            bus.next_frame(Bformat::zero(), Bformat::zero());
+        }
+        assert!((level(&mut bus, Bformat::zero()) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ended_sources_are_released() {
+        let (released, mut receiver) = queue::channel(16);
+        let (mut bus, _) = Bus::new(1000, released);
+        let (stream, _) = bstream(
+            SamplesBuffer::new(1, 1000, vec![1.0; 3]),
+            BstreamConfig::new(),
+        );
+        bus.add_stream(stream);
+
+        for _ in 0..10 {
+            bus.next_frame(Bformat::zero(), Bformat::zero());
+        }
+        assert!(matches!(receiver.recv(), Some(Released::Stream(_))));
+        assert!(receiver.recv().is_none());
+    }
+
+    #[test]
+    fn effects_process_the_bus_mix() {
+        let (mut bus, controller) = bus_with_source();
+
+        controller.add_effect(|x: Bformat| x * 3.0);
+        let input = Bformat::from_components([FRAC_1_SQRT_2; 16]);
+        assert!((level(&mut bus, input) - 6.0).abs() < 1e-6);
+
+        controller.clear_effects();
+        assert_eq!(level(&mut bus, Bformat::zero()), 1.0);
+    }
+
+    #[test]
+    fn storage_for_more_effects_comes_from_the_control_side() {
+        let (released, mut receiver) = queue::channel(16);
+        let (mut bus, controller) = Bus::new(1000, released);
+        let (stream, _) = bstream(Constant::new(1.0, 1000), BstreamConfig::new());
+        bus.add_stream(stream);
+
+        let n = 2 * EFFECT_CAPACITY + 1;
+        for _ in 0..n {
+            controller.add_effect(|x: Bformat| x * 2.0);
+        }
+        assert_eq!(level(&mut bus, Bformat::zero()), 2.0f32.powi(n as i32));
+
+        // the storage grew twice, and the old storage was handed back
+        assert!(matches!(receiver.recv(), Some(Released::Effects(_))));
+        assert!(matches!(receiver.recv(), Some(Released::Effects(_))));
+        assert!(receiver.recv().is_none());
+    }
+
+    #[test]
+    fn paused_buses_fade_out_and_are_silent() {
+        let (mut bus, controller) = bus_with_source();
+
+        controller.pause();
+        let fade: Vec<f32> = (0..5).map(|_| level(&mut bus, Bformat::zero())).collect();
+        for (level, expected) in fade.iter().zip([1.0, 0.8, 0.6, 0.4, 0.2]) {
+            assert!((level - expected).abs() < 1e-6, "{:?}", fade);
+        }
+        assert!(!bus.is_paused());
+
+        let (mix, send) = bus.next_frame(Bformat::zero(), Bformat::zero());
+        assert_eq!(mix, Bformat::zero());
+        assert_eq!(send, Bformat::zero());
+        assert!(bus.is_paused());
+
+        controller.resume();
+        assert_eq!(level(&mut bus, Bformat::zero()), 0.0);
+        assert!((level(&mut bus, Bformat::zero()) - 0.2).abs() < 1e-6);
+        assert!(!bus.is_paused());
+    }
+}
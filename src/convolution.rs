@@ -0,0 +1,281 @@
+//! Real-time convolution with long impulse responses
+//!
+//! The convolution engine uses a uniformly partitioned overlap-save scheme. The first partition
+//! of each impulse response is applied directly in the time domain, which avoids the latency of
+//! block-based processing. All further partitions are applied in the frequency domain, using a
+//! frequency-domain delay line of past input spectra.
+
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+
+/// Number of samples per partition
+pub const PARTITION_SIZE: usize = 64;
+
+/// Convolve multiple input channels with a matrix of impulse responses.
+///
+/// Each output channel is the sum of all input channels, each convolved with its own impulse
+/// response.
+pub struct Convolver {
+    n_inputs: usize,
+    n_outputs: usize,
+
+    /// first partition of every filter, time reversed, indexed by `[input][output]`
+    heads: Vec<Vec<Vec<f32>>>,
+
+    /// spectra of the remaining partitions, indexed by `[partition][input][output]`
+    tails: Vec<Vec<Vec<Vec<Complex<f32>>>>>,
+
+    /// last two blocks of input samples per input channel
+    input_buffers: Vec<Vec<f32>>,
+
+    /// spectra of past input blocks per input channel, newest at `fdl_pos`
+    fdl: Vec<Vec<Vec<Complex<f32>>>>,
+    fdl_pos: usize,
+
+    /// tail contribution to the output of the current block, per output channel
+    tail_outputs: Vec<Vec<f32>>,
+
+    pos: usize,
+
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+    fft_input: Vec<f32>,
+    fft_output: Vec<f32>,
+    accumulator: Vec<Complex<f32>>,
+    fft_scratch: Vec<Complex<f32>>,
+    ifft_scratch: Vec<Complex<f32>>,
+}
+
+impl Convolver {
+    /// Construct a convolver from impulse responses indexed by `[input][output]`.
+    ///
+    /// Impulse responses may have different lengths.
+    pub fn new(filters: &[Vec<Vec<f32>>]) -> Self {
+        let n_inputs = filters.len();
+        let n_outputs = filters.first().map(Vec::len).unwrap_or(0);
+        let b = PARTITION_SIZE;
+
+        let max_len = filters
+            .iter()
+            .flat_map(|f| f.iter().map(Vec::len))
+            .max()
+            .unwrap_or(0);
+        let n_tail = max_len.saturating_sub(b).div_ceil(b);
+
+        let mut planner = RealFftPlanner::new();
+        let fft = planner.plan_fft_forward(2 * b);
+        let ifft = planner.plan_fft_inverse(2 * b);
+
+        let heads = filters
+            .iter()
+            .map(|fs| {
+                fs.iter()
+                    .map(|f| {
+                        let mut head = vec![0.0; b];
+                        for (h, x) in head.iter_mut().rev().zip(f) {
+                            *h = *x;
+                        }
+                        head
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // The inverse transform is not normalized, so the scaling is folded into the filters.
+        let scale = 1.0 / (2 * b) as f32;
+        let mut fft_input = fft.make_input_vec();
+        let mut fft_scratch = fft.make_scratch_vec();
+        let tails = (0..n_tail)
+            .map(|p| {
+                filters
+                    .iter()
+                    .map(|fs| {
+                        fs.iter()
+                            .map(|f| {
+                                let start = (p + 1) * b;
+                                for (i, x) in fft_input.iter_mut().enumerate() {
+                                    *x = if i < b {
+                                        f.get(start + i).map(|h| h * scale).unwrap_or(0.0)
+                                    } else {
+                                        0.0
+                                    };
+                                }
+                                let mut spectrum = fft.make_output_vec();
+                                fft.process_with_scratch(
+                                    &mut fft_input,
+                                    &mut spectrum,
+                                    &mut fft_scratch,
+                                )
+                                .expect("FFT buffer sizes are consistent");
+                                spectrum
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Convolver {
+            n_inputs,
+            n_outputs,
+            heads,
+            tails,
+            input_buffers: vec![vec![0.0; 2 * b]; n_inputs],
+            fdl: vec![vec![fft.make_output_vec(); n_inputs]; n_tail],
+            fdl_pos: 0,
+            tail_outputs: vec![vec![0.0; b]; n_outputs],
+            pos: 0,
+            fft_input,
+            fft_output: ifft.make_output_vec(),
+            accumulator: fft.make_output_vec(),
+            fft_scratch,
+            ifft_scratch: ifft.make_scratch_vec(),
+            fft,
+            ifft,
+        }
+    }
+
+    /// Process one frame of input samples and produce one frame of output samples.
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let b = PARTITION_SIZE;
+
+        for (x, buffer) in input.iter().zip(&mut self.input_buffers) {
+            buffer[b + self.pos] = *x;
+        }
+
+        for (o, y) in output.iter_mut().enumerate().take(self.n_outputs) {
+            *y = self.tail_outputs[o][self.pos];
+        }
+
+        // the head filters are time reversed, so they can be applied to the last `b` inputs
+        for (buffer, heads) in self.input_buffers.iter().zip(&self.heads) {
+            let recent = &buffer[self.pos + 1..=b + self.pos];
+            for (y, head) in output.iter_mut().zip(heads) {
+                *y += head.iter().zip(recent).map(|(h, x)| h * x).sum::<f32>();
+            }
+        }
+
+        self.pos += 1;
+        if self.pos == b {
+            self.pos = 0;
+            self.process_block();
+        }
+    }
+
+    /// Compute the tail contribution to the next block of output
+    fn process_block(&mut self) {
+        let b = PARTITION_SIZE;
+
+        if self.tails.is_empty() {
+            for buffer in &mut self.input_buffers {
+                buffer.copy_within(b.., 0);
+            }
+            return;
+        }
+
+        let n_fdl = self.fdl.len();
+        self.fdl_pos = (self.fdl_pos + n_fdl - 1) % n_fdl;
+
+        for (buffer, spectrum) in self
+            .input_buffers
+            .iter_mut()
+            .zip(&mut self.fdl[self.fdl_pos])
+        {
+            self.fft_input.copy_from_slice(buffer);
+            self.fft
+                .process_with_scratch(&mut self.fft_input, spectrum, &mut self.fft_scratch)
+                .expect("FFT buffer sizes are consistent");
+            buffer.copy_within(b.., 0);
+        }
+
+        for o in 0..self.n_outputs {
+            for a in &mut self.accumulator {
+                *a = Complex::new(0.0, 0.0);
+            }
+
+            for (p, partition) in self.tails.iter().enumerate() {
+                let spectra = &self.fdl[(self.fdl_pos + p) % n_fdl];
+                for (x, filters) in spectra.iter().zip(partition).take(self.n_inputs) {
+                    for ((a, xk), hk) in self.accumulator.iter_mut().zip(x).zip(&filters[o]) {
+                        *a += xk * hk;
+                    }
+                }
+            }
+
+            // numerical noise may leave tiny imaginary parts that the inverse FFT rejects
+            self.accumulator[0].im = 0.0;
+            self.accumulator[b].im = 0.0;
+
+            self.ifft
+                .process_with_scratch(
+                    &mut self.accumulator,
+                    &mut self.fft_output,
+                    &mut self.ifft_scratch,
+                )
+                .expect("FFT buffer sizes are consistent");
+
+            // overlap-save: only the second half of the block is free of circular aliasing
+            self.tail_outputs[o].copy_from_slice(&self.fft_output[b..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    fn direct_convolution(input: &[f32], filter: &[f32]) -> Vec<f32> {
+        (0..input.len())
+            .map(|n| {
+                filter
+                    .iter()
+                    .enumerate()
+                    .filter(|(k, _)| *k <= n)
+                    .map(|(k, h)| h * input[n - k])
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn partitioned_convolution_matches_direct_convolution() {
+        let mut rng = SmallRng::seed_from_u64(42);
+        let mut random =
+            |n: usize| -> Vec<f32> { (0..n).map(|_| rng.gen_range(-1.0..1.0)).collect() };
+
+        let lengths = [1, 63, 64, 65, 300];
+        let filters: Vec<Vec<Vec<f32>>> = lengths
+            .iter()
+            .map(|&n| vec![random(n), random(n / 2 + 1)])
+            .collect();
+        let inputs: Vec<Vec<f32>> = lengths.iter().map(|_| random(1000)).collect();
+
+        let mut convolver = Convolver::new(&filters);
+        let mut outputs = vec![vec![]; 2];
+        for n in 0..1000 {
+            let frame: Vec<f32> = inputs.iter().map(|x| x[n]).collect();
+            let mut out = [0.0; 2];
+            convolver.process(&frame, &mut out);
+            outputs[0].push(out[0]);
+            outputs[1].push(out[1]);
+        }
+
+        for (o, output) in outputs.iter().enumerate() {
+            let mut expected = vec![0.0; 1000];
+            for (input, filters) in inputs.iter().zip(&filters) {
+                for (e, y) in expected
+                    .iter_mut()
+                    .zip(direct_convolution(input, &filters[o]))
+                {
+                    *e += y;
+                }
+            }
+            for (y, e) in output.iter().zip(&expected) {
+                assert!((y - e).abs() < 1e-3, "{} != {}", y, e);
+            }
+        }
+    }
+}
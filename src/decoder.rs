@@ -0,0 +1,420 @@
+//! Decoding matrices for loudspeaker layouts
+//!
+//! A decoder assigns each speaker a set of weights, such that the speaker signal is the dot
+//! product of the weights and a *B-format* sample.
+
+use crate::bformat::{fuma_to_sn3d, AmbisonicOrder, Bweights, COMPONENT_DEGREES, MAX_CHANNELS};
+use crate::linalg;
+use crate::sphere;
+
+/// Components that can be reproduced by a horizontal layout
+const HORIZONTAL_COMPONENTS: [usize; 7] = [0, 1, 2, 7, 8, 14, 15];
+
+/// Number of virtual speakers AllRAD uses for three-dimensional layouts
+const ALLRAD_VIRTUAL_SPEAKERS: usize = 240;
+
+/// Number of virtual speakers AllRAD uses for horizontal layouts
+const ALLRAD_VIRTUAL_RING: usize = 72;
+
+/// Relative regularization of pseudo-inverses
+const REGULARIZATION: f64 = 1e-9;
+
+/// Method for computing the decoding matrix of a loudspeaker layout
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DecoderMethod {
+    /// Sample the spherical harmonics in the speaker directions.
+    ///
+    /// Simple and robust, but only accurate for regular layouts.
+    Sampling,
+
+    /// Choose speaker signals whose re-encoding reproduces the sound field.
+    ///
+    /// Accurate for regular layouts, but may result in uneven loudness for irregular ones.
+    ModeMatching,
+
+    /// All-round ambisonic decoding: decode to a dense set of virtual speakers, which are then
+    /// panned to the real speakers using vector base amplitude panning.
+    ///
+    /// Well suited for irregular layouts such as 5.1 or 7.1.4.
+    #[default]
+    AllRad,
+}
+
+/// Whether all directions lie in the horizontal plane
+fn is_horizontal(directions: &[[f32; 3]]) -> bool {
+    directions.iter().all(|d| d[2].abs() < 1e-3)
+}
+
+/// Components that a layout is able to reproduce at the given order
+fn components(order: AmbisonicOrder, horizontal: bool) -> Vec<usize> {
+    let n = order.channels();
+    if horizontal {
+        HORIZONTAL_COMPONENTS
+            .iter()
+            .cloned()
+            .filter(|&c| c < n)
+            .collect()
+    } else {
+        (0..n).collect()
+    }
+}
+
+/// Compute decoder weights for speakers in the given (normalized) directions.
+pub fn design(
+    directions: &[[f32; 3]],
+    order: AmbisonicOrder,
+    method: DecoderMethod,
+) -> Vec<Bweights> {
+    let horizontal = is_horizontal(directions);
+    let components = components(order, horizontal);
+
+    match method {
+        DecoderMethod::Sampling => sampling(directions, &components, horizontal),
+        DecoderMethod::ModeMatching => mode_matching(directions, &components),
+        DecoderMethod::AllRad => {
+            let (virtual_speakers, virtual_decoder) = if horizontal {
+                let ring: Vec<_> = (0..ALLRAD_VIRTUAL_RING)
+                    .map(|i| {
+                        let phi =
+                            2.0 * std::f32::consts::PI * i as f32 / ALLRAD_VIRTUAL_RING as f32;
+                        [phi.cos(), phi.sin(), 0.0]
+                    })
+                    .collect();
+                let decoder = sampling(&ring, &components, true);
+                (ring, decoder)
+            } else {
+                let points = sphere::fibonacci(ALLRAD_VIRTUAL_SPEAKERS);
+                let decoder = mode_matching(&points, &components);
+                (points, decoder)
+            };
+
+            let panner = Vbap::new(directions, horizontal);
+
+            let mut weights = vec![[0.0; MAX_CHANNELS]; directions.len()];
+            for (dir, virtual_weights) in virtual_speakers.iter().zip(&virtual_decoder) {
+                for (w, g) in weights.iter_mut().zip(panner.gains(*dir)) {
+                    for (x, v) in w.iter_mut().zip(virtual_weights.components()) {
+                        *x += g * v;
+                    }
+                }
+            }
+
+            weights
+                .iter()
+                .map(|w| w.iter().cloned().collect())
+                .collect()
+        }
+    }
+}
+
+/// Sampling decoder
+///
+/// The FuMa weights of each speaker are rescaled to N3D normalization, so that regular layouts
+/// reproduce a plane wave with unit gain.
+fn sampling(directions: &[[f32; 3]], components: &[usize], horizontal: bool) -> Vec<Bweights> {
+    let n = directions.len() as f32;
+    directions
+        .iter()
+        .map(|&d| {
+            let encoding = Bweights::from_direction(d);
+            let mut weights = [0.0; MAX_CHANNELS];
+            for &c in components {
+                // mean square of the component over the circle or the sphere
+                let sn3d = fuma_to_sn3d(c);
+                let norm = if horizontal {
+                    2.0
+                } else {
+                    sn3d * sn3d * (2 * COMPONENT_DEGREES[c] + 1) as f32
+                };
+                weights[c] = norm * encoding.components()[c] / n;
+            }
+            weights.iter().cloned().collect()
+        })
+        .collect()
+}
+
+/// Mode-matching decoder
+///
+/// Re-encoding the speaker signals reproduces the *B-format* components (in a least-squares
+/// sense if the layout cannot represent them exactly).
+fn mode_matching(directions: &[[f32; 3]], components: &[usize]) -> Vec<Bweights> {
+    // encoding matrix: one row per B-format component, one column per speaker
+    let encoder: Vec<Vec<f64>> = components
+        .iter()
+        .map(|&c| {
+            directions
+                .iter()
+                .map(|&d| Bweights::from_direction(d).components()[c] as f64)
+                .collect()
+        })
+        .collect();
+
+    let trace: f64 = encoder.iter().flatten().map(|x| x * x).sum();
+    let lambda = REGULARIZATION * trace / components.len() as f64;
+
+    linalg::regularized_pseudo_inverse(&encoder, lambda)
+        .into_iter()
+        .map(|row| {
+            let mut weights = [0.0; MAX_CHANNELS];
+            for (&c, x) in components.iter().zip(row) {
+                weights[c] = x as f32;
+            }
+            weights.iter().cloned().collect()
+        })
+        .collect()
+}
+
+/// Vector base amplitude panning
+struct Vbap {
+    speakers: Vec<[f64; 3]>,
+
+    /// number of real speakers; any further speakers are imaginary and receive no signal
+    n_real: usize,
+
+    /// speaker pairs or triplets, and the inverse of the matrix formed by their directions
+    bases: Vec<(Vec<usize>, linalg::Matrix)>,
+}
+
+impl Vbap {
+    fn new(directions: &[[f32; 3]], horizontal: bool) -> Self {
+        let mut speakers: Vec<[f64; 3]> = directions
+            .iter()
+            .map(|d| [d[0] as f64, d[1] as f64, d[2] as f64])
+            .collect();
+        let n_real = speakers.len();
+
+        let groups = if horizontal {
+            // adjacent speakers in order of their azimuth
+            let mut order: Vec<usize> = (0..n_real).collect();
+            order.sort_by(|&a, &b| {
+                let azimuth = |d: &[f64; 3]| d[1].atan2(d[0]);
+                azimuth(&speakers[a]).total_cmp(&azimuth(&speakers[b]))
+            });
+            (0..n_real)
+                .map(|i| vec![order[i], order[(i + 1) % n_real]])
+                .collect()
+        } else {
+            // imaginary speakers close gaps above and below the layout
+            if speakers.iter().all(|d| d[2] > -0.5) {
+                speakers.push([0.0, 0.0, -1.0]);
+            }
+            if speakers.iter().all(|d| d[2] < 0.5) {
+                speakers.push([0.0, 0.0, 1.0]);
+            }
+            convex_hull(&speakers)
+        };
+
+        let dims = if horizontal { 2 } else { 3 };
+        let bases = groups
+            .into_iter()
+            .filter_map(|group: Vec<usize>| {
+                let matrix: linalg::Matrix = group
+                    .iter()
+                    .map(|&i| speakers[i][..dims].to_vec())
+                    .collect();
+                let inverse = linalg::invert(&matrix)?;
+                Some((group, inverse))
+            })
+            .collect();
+
+        Vbap {
+            speakers,
+            n_real,
+            bases,
+        }
+    }
+
+    /// Energy normalized gains of the real speakers for a source in the given direction
+    fn gains(&self, direction: [f32; 3]) -> Vec<f32> {
+        let direction = [
+            direction[0] as f64,
+            direction[1] as f64,
+            direction[2] as f64,
+        ];
+
+        let best = self
+            .bases
+            .iter()
+            .map(|(group, inverse)| {
+                let gains: Vec<f64> = (0..group.len())
+                    .map(|j| (0..group.len()).map(|i| direction[i] * inverse[i][j]).sum())
+                    .collect();
+                (group, gains)
+            })
+            .max_by(|(_, a), (_, b)| {
+                let min = |g: &[f64]| g.iter().cloned().fold(f64::INFINITY, f64::min);
+                min(a).total_cmp(&min(b))
+            });
+
+        let mut output = vec![0.0f64; self.speakers.len()];
+        if let Some((group, gains)) = best {
+            for (&i, g) in group.iter().zip(gains) {
+                output[i] = g.max(0.0);
+            }
+        }
+
+        let norm = output.iter().map(|g| g * g).sum::<f64>().sqrt();
+        if norm > 1e-9 {
+            for g in &mut output {
+                *g /= norm;
+            }
+        } else {
+            // direction is not covered by the layout; use the nearest speaker
+            let nearest = (0..self.speakers.len())
+                .max_by(|&a, &b| {
+                    let dot =
+                        |s: &[f64; 3]| s.iter().zip(&direction).map(|(s, d)| s * d).sum::<f64>();
+                    dot(&self.speakers[a]).total_cmp(&dot(&self.speakers[b]))
+                })
+                .expect("layout has at least one speaker");
+            output[nearest] = 1.0;
+        }
+
+        output[..self.n_real].iter().map(|&g| g as f32).collect()
+    }
+}
+
+/// Triangular faces of the convex hull of a set of points
+///
+/// Computed by brute force, which is fast enough for speaker layouts.
+fn convex_hull(points: &[[f64; 3]]) -> Vec<Vec<usize>> {
+    let sub = |a: &[f64; 3], b: &[f64; 3]| [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    let dot = |a: &[f64; 3], b: &[f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+    let mut faces = vec![];
+    for i in 0..points.len() {
+        for j in i + 1..points.len() {
+            for k in j + 1..points.len() {
+                let u = sub(&points[j], &points[i]);
+                let v = sub(&points[k], &points[i]);
+                let normal = [
+                    u[1] * v[2] - u[2] * v[1],
+                    u[2] * v[0] - u[0] * v[2],
+                    u[0] * v[1] - u[1] * v[0],
+                ];
+                if dot(&normal, &normal) < 1e-12 {
+                    continue;
+                }
+
+                let sides: Vec<f64> = points
+                    .iter()
+                    .map(|p| dot(&sub(p, &points[i]), &normal))
+                    .collect();
+                if sides.iter().all(|&s| s < 1e-9) || sides.iter().all(|&s| s > -1e-9) {
+                    faces.push(vec![i, j, k]);
+                }
+            }
+        }
+    }
+    faces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(n: usize) -> Vec<[f32; 3]> {
+        (0..n)
+            .map(|i| {
+                let phi = 2.0 * std::f32::consts::PI * i as f32 / n as f32;
+                [phi.cos(), phi.sin(), 0.0]
+            })
+            .collect()
+    }
+
+    fn speaker_signals(decoder: &[Bweights], source: [f32; 3]) -> Vec<f32> {
+        let encoded = Bweights::from_direction(source);
+        decoder
+            .iter()
+            .map(|w| {
+                w.components()
+                    .iter()
+                    .zip(encoded.components())
+                    .map(|(a, b)| a * b)
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn loudest(signals: &[f32]) -> usize {
+        (0..signals.len())
+            .max_by(|&a, &b| signals[a].total_cmp(&signals[b]))
+            .unwrap()
+    }
+
+    #[test]
+    fn decoders_reproduce_plane_waves() {
+        for &order in &[
+            AmbisonicOrder::First,
+            AmbisonicOrder::Second,
+            AmbisonicOrder::Third,
+        ] {
+            let directions = sphere::design_for_order(order);
+            let decoder = design(&directions, order, DecoderMethod::ModeMatching);
+
+            let source = Bweights::from_direction([0.3, 0.5, -0.2]).scale(1.0);
+            let mut reencoded = [0.0; 16];
+            for (speaker, dir) in decoder.iter().zip(&directions) {
+                let signal = speaker.dot(source);
+                let encoded = Bweights::from_direction(*dir);
+                for (r, e) in reencoded.iter_mut().zip(encoded.components()) {
+                    *r += signal * e;
+                }
+            }
+
+            for (r, s) in reencoded
+                .iter()
+                .zip(source.components())
+                .take(order.channels())
+            {
+                assert!((r - s).abs() < 1e-4, "{:?}: {} != {}", order, r, s);
+            }
+        }
+    }
+
+    #[test]
+    fn ring_decoders_point_at_sources() {
+        for &method in &[
+            DecoderMethod::Sampling,
+            DecoderMethod::ModeMatching,
+            DecoderMethod::AllRad,
+        ] {
+            for &n in &[8, 16] {
+                let speakers = ring(n);
+                let decoder = design(&speakers, AmbisonicOrder::Third, method);
+                for (i, &dir) in speakers.iter().enumerate() {
+                    let signals = speaker_signals(&decoder, dir);
+                    assert_eq!(loudest(&signals), i, "{:?} with {} speakers", method, n);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn regular_decoders_have_unit_gain() {
+        let speakers = ring(8);
+        for &method in &[DecoderMethod::Sampling, DecoderMethod::ModeMatching] {
+            let decoder = design(&speakers, AmbisonicOrder::Third, method);
+            let signals = speaker_signals(&decoder, [0.3, 0.7, 0.0]);
+            let sum: f32 = signals.iter().sum();
+            assert!((sum - 1.0).abs() < 1e-4, "{:?}: {}", method, sum);
+        }
+    }
+
+    #[test]
+    fn allrad_handles_dome_layouts() {
+        let mut speakers = ring(8);
+        speakers.extend([
+            sphere::normalize([1.0, 1.0, 1.0]),
+            sphere::normalize([-1.0, 1.0, 1.0]),
+            sphere::normalize([-1.0, -1.0, 1.0]),
+            sphere::normalize([1.0, -1.0, 1.0]),
+        ]);
+        let decoder = design(&speakers, AmbisonicOrder::Third, DecoderMethod::AllRad);
+        for (i, &dir) in speakers.iter().enumerate() {
+            let signals = speaker_signals(&decoder, dir);
+            assert!(signals.iter().all(|s| s.is_finite()));
+            assert_eq!(loudest(&signals), i);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Propagation delay of sound sources
+
+use crate::resampler::{Interpolation, Resampler};
+
+/// Fraction of the remaining difference between delay and target applied per sample
+const SMOOTHING: f32 = 0.0005;
+
+/// Variable fractional delay line
+///
+/// The buffer is allocated for the maximum delay up front, so that processing never allocates.
+/// The delay follows its target smoothly. Between target updates, the target moves with a
+/// constant slope (in samples of delay per sample), so that moving sources produce a steady
+/// Doppler shift. Fractional delays are read with the interpolation of a `Resampler`, which
+/// needs a few samples after the one it interpolates, so the delay is at least its `tail`.
+pub(crate) struct DelayLine {
+    buffer: Vec<f32>,
+    position: usize,
+    delay: f32,
+    target: f32,
+    slope: f32,
+    min_delay: f32,
+    max_delay: f32,
+    kernel: Resampler,
+}
+
+impl DelayLine {
+    /// Construct a delay line that starts at `delay` samples and never exceeds `max_delay`
+    pub(crate) fn new(delay: f32, max_delay: f32, interpolation: Interpolation) -> Self {
+        let kernel = Resampler::new(interpolation, 1.0);
+        let min_delay = kernel.tail() as f32;
+        let max_delay = max_delay.max(min_delay);
+        let delay = delay.clamp(min_delay, max_delay);
+        DelayLine {
+            buffer: vec![0.0; max_delay as usize + 2 + kernel.tail()],
+            position: 0,
+            delay,
+            target: delay,
+            slope: 0.0,
+            min_delay,
+            max_delay,
+            kernel,
+        }
+    }
+
+    /// Number of samples after which an input sample no longer affects the output at the
+    /// current delay
+    pub(crate) fn ring_out(&self) -> usize {
+        self.delay as usize + 2 + self.kernel.tail()
+    }
+
+    pub(crate) fn set_target(&mut self, delay: f32) {
+        self.target = delay.clamp(self.min_delay, self.max_delay);
+    }
+
+    /// Set the slope, which also adapts the anti-aliasing of the interpolation to the rate
+    /// `1 - slope` at which the input is read
+    pub(crate) fn set_slope(&mut self, slope: f32) {
+        self.slope = slope;
+        self.kernel.set_speed(1.0 - slope);
+    }
+
+    /// Feed the next input sample and return the delayed output
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        self.delay += self.slope + (self.target - self.delay) * SMOOTHING;
+        self.delay = self.delay.clamp(self.min_delay, self.max_delay);
+        self.target = (self.target + self.slope).clamp(self.min_delay, self.max_delay);
+
+        let len = self.buffer.len();
+        self.buffer[self.position] = x;
+
+        // interpolate between the samples `i + 1` and `i` places back
+        let i = self.delay as usize;
+        let f = self.delay - i as f32;
+        let tail = self.kernel.tail();
+        let (buffer, position) = (&self.buffer, self.position);
+        let y = self.kernel.interpolate_from(
+            |k| buffer[(position + 2 * len + k - i - 1 - tail) % len],
+            1.0 - f,
+        );
+
+        self.position = (self.position + 1) % len;
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn impulse_response(line: &mut DelayLine, n: usize) -> Vec<f32> {
+        (0..n)
+            .map(|i| line.process(if i == 0 { 1.0 } else { 0.0 }))
+            .collect()
+    }
+
+    #[test]
+    fn impulses_are_delayed() {
+        let mut line = DelayLine::new(3.0, 100.0, Interpolation::Linear);
+        assert_eq!(impulse_response(&mut line, 5), [0.0, 0.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn longer_delays_read_older_samples() {
+        let mut line = DelayLine::new(2.0, 100.0, Interpolation::Linear);
+        for x in 1..=4 {
+            line.process(x as f32);
+        }
+        line.delay = 3.0;
+        line.target = 3.0;
+        assert_eq!(line.process(5.0), 2.0);
+
+        line.delay = 100.0;
+        line.target = 100.0;
+        assert_eq!(line.process(6.0), 0.0);
+    }
+
+    #[test]
+    fn slope_changes_the_delay_steadily() {
+        let mut line = DelayLine::new(10.0, 100.0, Interpolation::Linear);
+        line.set_slope(0.1);
+        for _ in 0..100 {
+            line.process(0.0);
+        }
+        assert!((line.delay - 20.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn all_interpolations_delay_impulses() {
+        for interpolation in [
+            Interpolation::Linear,
+            Interpolation::Cubic,
+            Interpolation::Sinc,
+        ] {
+            let mut line = DelayLine::new(10.0, 100.0, interpolation);
+            let response = impulse_response(&mut line, 30);
+            let peak = (0..response.len())
+                .max_by(|&a, &b| response[a].total_cmp(&response[b]))
+                .unwrap();
+            assert_eq!(peak, 10);
+            assert!((response.iter().sum::<f32>() - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn fractional_delays_use_the_selected_interpolation() {
+        // linear interpolation halfway between samples attenuates high frequencies
+        let amplitude = |interpolation| {
+            let mut line = DelayLine::new(10.5, 100.0, interpolation);
+            (0..200)
+                .map(|i| line.process((0.8 * std::f32::consts::PI * i as f32).sin()))
+                .skip(50)
+                .fold(0.0f32, |a, y| a.max(y.abs()))
+        };
+
+        let linear = amplitude(Interpolation::Linear);
+        assert!(linear < 0.35);
+        assert!(amplitude(Interpolation::Cubic) > linear + 0.1);
+        assert!(amplitude(Interpolation::Sinc) > 0.8);
+    }
+}
@@ -0,0 +1,150 @@
+//! Direction-dependent radiation of sound sources
+
+use crate::bformat::norm;
+
+/// Pattern with which a source radiates sound around its facing direction.
+///
+/// The cone pattern corresponds to the sound cones of OpenAL: the gain is 1 inside the inner
+/// cone, `outer_gain` outside the outer cone, and changes linearly with the angle in between.
+/// The polar patterns are first-order microphone patterns, mirrored for sources.
+///
+/// The default pattern is omnidirectional.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Directivity {
+    pattern: Pattern,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Pattern {
+    Omni,
+    Cone {
+        inner: f32,
+        outer: f32,
+        outer_gain: f32,
+    },
+    Polar(f32),
+}
+
+impl Directivity {
+    /// Sources radiate equally in all directions.
+    pub fn omni() -> Self {
+        Directivity {
+            pattern: Pattern::Omni,
+        }
+    }
+
+    /// Sound cones with full opening angles in degrees.
+    ///
+    /// Listeners outside the outer cone hear the source at `outer_gain`.
+    pub fn cone(inner_angle: f32, outer_angle: f32, outer_gain: f32) -> Self {
+        let inner = inner_angle.to_radians() / 2.0;
+        Directivity {
+            pattern: Pattern::Cone {
+                inner,
+                outer: (outer_angle.to_radians() / 2.0).max(inner),
+                outer_gain,
+            },
+        }
+    }
+
+    /// Heart-shaped pattern that is silent directly behind the source.
+    ///
+    /// `gain = (1 + cos(angle)) / 2`
+    pub fn cardioid() -> Self {
+        Self::polar(0.5)
+    }
+
+    /// Narrower pattern than the cardioid with a small lobe behind the source.
+    ///
+    /// `gain = |0.37 + 0.63 cos(angle)|`
+    pub fn supercardioid() -> Self {
+        Self::polar(0.37)
+    }
+
+    /// First-order pattern with given omnidirectional part between 0 and 1.
+    ///
+    /// `gain = |omni + (1 - omni) cos(angle)|`
+    pub fn polar(omni: f32) -> Self {
+        Directivity {
+            pattern: Pattern::Polar(omni),
+        }
+    }
+
+    /// Compute the gain of a source facing `direction` at `position` relative to the listener.
+    ///
+    /// The direction does not need to be normalized. Sources without a direction, or at the
+    /// listener's position, are not attenuated.
+    pub fn gain(&self, direction: [f32; 3], position: [f32; 3]) -> f32 {
+        let d = norm(direction) * norm(position);
+        if d < 1e-6 {
+            return 1.0;
+        }
+
+        // the listener is at -position as seen from the source
+        let cos =
+            -(direction[0] * position[0] + direction[1] * position[1] + direction[2] * position[2])
+                / d;
+        let cos = cos.clamp(-1.0, 1.0);
+
+        match self.pattern {
+            Pattern::Omni => 1.0,
+            Pattern::Cone {
+                inner,
+                outer,
+                outer_gain,
+            } => {
+                let angle = cos.acos();
+                if angle <= inner {
+                    1.0
+                } else if angle >= outer {
+                    outer_gain
+                } else {
+                    1.0 + (outer_gain - 1.0) * (angle - inner) / (outer - inner)
+                }
+            }
+            Pattern::Polar(omni) => (omni + (1.0 - omni) * cos).abs(),
+        }
+    }
+}
+
+impl Default for Directivity {
+    fn default() -> Self {
+        Self::omni()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cones_interpolate_between_inner_and_outer_angle() {
+        let cone = Directivity::cone(90.0, 180.0, 0.2);
+        let facing = [0.0, -1.0, 0.0];
+
+        // the source is in front of the listener and faces it
+        assert_eq!(cone.gain(facing, [0.0, 1.0, 0.0]), 1.0);
+        // the listener is 67.5 degrees off the source's axis
+        let off_axis = [
+            -(67.5f32.to_radians().sin()),
+            67.5f32.to_radians().cos(),
+            0.0,
+        ];
+        assert!((cone.gain(facing, off_axis) - 0.6).abs() < 1e-5);
+        assert_eq!(cone.gain(facing, [0.0, -1.0, 0.0]), 0.2);
+    }
+
+    #[test]
+    fn polar_patterns_follow_the_cosine_of_the_angle() {
+        let facing = [1.0, 0.0, 0.0];
+        let cardioid = Directivity::cardioid();
+        assert_eq!(cardioid.gain(facing, [-2.0, 0.0, 0.0]), 1.0);
+        assert!((cardioid.gain(facing, [0.0, 2.0, 0.0]) - 0.5).abs() < 1e-6);
+        assert_eq!(cardioid.gain(facing, [2.0, 0.0, 0.0]), 0.0);
+
+        let supercardioid = Directivity::supercardioid();
+        assert!((supercardioid.gain(facing, [2.0, 0.0, 0.0]) - 0.26).abs() < 1e-6);
+
+        assert_eq!(cardioid.gain([0.0; 3], [2.0, 0.0, 0.0]), 1.0);
+    }
+}
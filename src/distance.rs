@@ -0,0 +1,195 @@
+//! Attenuation of sound sources with distance
+
+use std::f32;
+use std::sync::Arc;
+
+/// Model for attenuating sound sources with distance from the listener.
+///
+/// The models correspond to the distance models of OpenAL. They are parameterized by a
+/// *reference distance*, at which the gain is 1, a *maximum distance*, beyond which the gain
+/// no longer changes, and a *rolloff factor* that controls how fast the gain decreases.
+///
+/// The default model is inverse-clamped with a reference distance of 1, an infinite maximum
+/// distance and a rolloff factor of 1. That is, the gain is `1 / distance` for sources further
+/// than 1 unit from the listener.
+#[derive(Clone)]
+pub struct DistanceModel {
+    attenuation: Attenuation,
+    reference_distance: f32,
+    max_distance: f32,
+    rolloff_factor: f32,
+}
+
+#[derive(Clone)]
+enum Attenuation {
+    None,
+    Inverse,
+    InverseClamped,
+    Linear,
+    Exponential,
+    Custom(Arc<dyn Fn(f32) -> f32 + Send + Sync>),
+}
+
+impl DistanceModel {
+    fn new(attenuation: Attenuation) -> Self {
+        DistanceModel {
+            attenuation,
+            reference_distance: 1.0,
+            max_distance: f32::INFINITY,
+            rolloff_factor: 1.0,
+        }
+    }
+
+    /// Sources are not attenuated with distance.
+    pub fn none() -> Self {
+        Self::new(Attenuation::None)
+    }
+
+    /// Inverse distance law.
+    ///
+    /// `gain = ref / max(ref, ref + rolloff * (distance - ref))`
+    ///
+    /// Unlike the clamped model, the maximum distance is ignored. The denominator is limited to
+    /// the reference distance, so that sources at or close to the listener keep a gain of 1.
+    pub fn inverse() -> Self {
+        Self::new(Attenuation::Inverse)
+    }
+
+    /// Inverse distance law, with the distance clamped between reference and maximum distance.
+    pub fn inverse_clamped() -> Self {
+        Self::new(Attenuation::InverseClamped)
+    }
+
+    /// Linear decrease of gain, with the distance clamped between reference and maximum
+    /// distance.
+    ///
+    /// `gain = 1 - rolloff * (distance - ref) / (max - ref)`
+    ///
+    /// With a rolloff factor of 1, sources fade out completely at the maximum distance.
+    pub fn linear() -> Self {
+        Self::new(Attenuation::Linear)
+    }
+
+    /// Exponential decrease of gain, with the distance clamped between reference and maximum
+    /// distance.
+    ///
+    /// `gain = (distance / ref) ^ -rolloff`
+    pub fn exponential() -> Self {
+        Self::new(Attenuation::Exponential)
+    }
+
+    /// User-supplied attenuation curve that maps distance to gain.
+    ///
+    /// Reference distance, maximum distance, and rolloff factor are ignored.
+    pub fn custom<F>(curve: F) -> Self
+    where
+        F: Fn(f32) -> f32 + Send + Sync + 'static,
+    {
+        Self::new(Attenuation::Custom(Arc::new(curve)))
+    }
+
+    /// Set distance at which the gain is 1.
+    pub fn with_reference_distance(mut self, d: f32) -> Self {
+        self.reference_distance = d;
+        self
+    }
+
+    /// Set distance beyond which the gain no longer changes.
+    pub fn with_max_distance(mut self, d: f32) -> Self {
+        self.max_distance = d;
+        self
+    }
+
+    /// Set how fast the gain decreases with distance.
+    pub fn with_rolloff_factor(mut self, r: f32) -> Self {
+        self.rolloff_factor = r;
+        self
+    }
+
+    /// Compute the gain of a source at given distance from the listener.
+    pub fn gain(&self, distance: f32) -> f32 {
+        let r = self.reference_distance;
+        let clamped = distance.max(r).min(self.max_distance);
+
+        let gain = match &self.attenuation {
+            Attenuation::None => 1.0,
+            Attenuation::Inverse => r / (r + self.rolloff_factor * (distance - r)).max(r),
+            Attenuation::InverseClamped => r / (r + self.rolloff_factor * (clamped - r)),
+            Attenuation::Linear if self.max_distance <= r => 1.0,
+            Attenuation::Linear => {
+                1.0 - self.rolloff_factor * (clamped - r) / (self.max_distance - r)
+            }
+            Attenuation::Exponential => (clamped / r).powf(-self.rolloff_factor),
+            Attenuation::Custom(curve) => curve(distance),
+        };
+
+        if gain.is_finite() {
+            gain.max(0.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Default for DistanceModel {
+    fn default() -> Self {
+        Self::inverse_clamped()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_model_matches_inverse_distance_beyond_one_unit() {
+        let model = DistanceModel::default();
+        assert_eq!(model.gain(0.5), 1.0);
+        assert_eq!(model.gain(1.0), 1.0);
+        assert_eq!(model.gain(4.0), 0.25);
+    }
+
+    #[test]
+    fn linear_model_fades_out_at_max_distance() {
+        let model = DistanceModel::linear()
+            .with_reference_distance(10.0)
+            .with_max_distance(20.0);
+        assert_eq!(model.gain(5.0), 1.0);
+        assert_eq!(model.gain(15.0), 0.5);
+        assert_eq!(model.gain(20.0), 0.0);
+        assert_eq!(model.gain(100.0), 0.0);
+    }
+
+    #[test]
+    fn inverse_model_keeps_sources_at_the_listener_audible() {
+        let model = DistanceModel::inverse().with_rolloff_factor(2.0);
+        assert_eq!(model.gain(0.0), 1.0);
+        assert_eq!(model.gain(0.4), 1.0);
+        assert_eq!(model.gain(3.0), 0.2);
+    }
+
+    #[test]
+    fn linear_model_without_range_does_not_attenuate() {
+        let model = DistanceModel::linear()
+            .with_reference_distance(5.0)
+            .with_max_distance(5.0);
+        assert_eq!(model.gain(1.0), 1.0);
+        assert_eq!(model.gain(50.0), 1.0);
+    }
+
+    #[test]
+    fn exponential_model_uses_rolloff_as_exponent() {
+        let model = DistanceModel::exponential()
+            .with_rolloff_factor(2.0)
+            .with_max_distance(10.0);
+        assert_eq!(model.gain(2.0), 0.25);
+        assert_eq!(model.gain(20.0), 0.01);
+    }
+
+    #[test]
+    fn custom_curve_maps_distance_to_gain() {
+        let model = DistanceModel::custom(|d| if d < 3.0 { 1.0 } else { 0.0 });
+        assert_eq!(model.gain(2.0), 1.0);
+        assert_eq!(model.gain(3.0), 0.0);
+    }
+}
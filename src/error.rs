@@ -0,0 +1,115 @@
+//! Error types
+
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors that can occur while setting up an ambisonic context or loading its configuration
+///
+/// Some variants depend on enabled features, and new variants may be added in the future.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading a file failed
+    Io(io::Error),
+
+    /// A `.hrir` file is malformed
+    ParseHrir {
+        /// Line number (starting at 1) where the problem was detected
+        line: usize,
+        /// Description of the problem
+        message: String,
+    },
+
+    /// A SOFA file could not be loaded
+    #[cfg(feature = "sofa")]
+    Sofa(sofar::reader::Error),
+
+    /// A WAV file could not be read or written
+    #[cfg(feature = "wav")]
+    Wav(hound::Error),
+
+    /// The audio output stream could not be opened
+    Stream(rodio::StreamError),
+
+    /// The sound scene could not be played on the output stream
+    Play(rodio::PlayError),
+
+    /// The playback configuration was made for a different sample rate than the mix
+    SampleRateMismatch {
+        /// Sample rate of the mix
+        expected: u32,
+        /// Sample rate of the playback configuration
+        found: u32,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::ParseHrir { line, message } => {
+                write!(f, "invalid HRIR data in line {}: {}", line, message)
+            }
+            #[cfg(feature = "sofa")]
+            Error::Sofa(e) => write!(f, "cannot load SOFA file: {}", e),
+            #[cfg(feature = "wav")]
+            Error::Wav(e) => write!(f, "cannot process WAV file: {}", e),
+            Error::Stream(e) => write!(f, "cannot open audio output stream: {}", e),
+            Error::Play(e) => write!(f, "cannot play sound scene: {}", e),
+            Error::SampleRateMismatch { expected, found } => write!(
+                f,
+                "playback configuration has sample rate {} instead of {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::ParseHrir { .. } => None,
+            #[cfg(feature = "sofa")]
+            Error::Sofa(e) => Some(e),
+            #[cfg(feature = "wav")]
+            Error::Wav(e) => Some(e),
+            Error::Stream(e) => Some(e),
+            Error::Play(e) => Some(e),
+            Error::SampleRateMismatch { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "sofa")]
+impl From<sofar::reader::Error> for Error {
+    fn from(e: sofar::reader::Error) -> Self {
+        Error::Sofa(e)
+    }
+}
+
+#[cfg(feature = "wav")]
+impl From<hound::Error> for Error {
+    fn from(e: hound::Error) -> Self {
+        Error::Wav(e)
+    }
+}
+
+impl From<rodio::StreamError> for Error {
+    fn from(e: rodio::StreamError) -> Self {
+        Error::Stream(e)
+    }
+}
+
+impl From<rodio::PlayError> for Error {
+    fn from(e: rodio::PlayError) -> Self {
+        Error::Play(e)
+    }
+}
@@ -8,7 +8,19 @@ and playing the resulting spatial mix in real-time over a sound card.
 ### Features:
 - Realistic directional audio
 - Take `rodio` sound sources and place them in space
-- Doppler effect on moving sounds
+- Doppler effect on moving sounds and listeners, optionally with propagation delay and
+  high-quality resampling
+- Configurable distance attenuation and air absorption
+- Directional sources with sound cones or polar patterns
+- Spatially extended sources, such as waterfalls or rain
+- Near-field compensation of close sources
+- Occlusion and obstruction of sources behind obstacles
+- Click-free gain changes, fades, and muting of individual sources
+- Buses for grouping sources, with their own gain, pause, and effect chain
+- Reverb and early reflections of rectangular rooms
+- Listener orientation (e.g. for turning players or head-tracking)
+- World-space sources heard relative to a movable listener
+- Play and export *B-format* recordings in AmbiX format
 
 ## Usage Example
 
@@ -39,29 +51,87 @@ their audio playback equipment. This leads to a clear separation of audio scene
 rendering. For details, see [Wikipedia](https://en.wikipedia.org/wiki/Ambisonics).
 
 In its current state, the library allows spatial composition of single-channel `rodio` sources
-into a first-order *B-format* stream. The chosen renderer then decodes the *B-format* stream
+into a *B-format* stream of first, second or third order. Higher orders result in sharper
+localisation, provided the renderer can make use of them. The chosen renderer then decodes the *B-format* stream
 into audio signals for playback.
 
 Currently, the following renderers are available:
 
-- Stereo: simple and efficient playback on two stereo speakers or headphones
-- HRTF: realistic 3D sound over headphones using head related transfer functions
+- Stereo: simple and efficient playback on two stereo speakers or headphones (first order only)
+- HRTF: realistic 3D sound over headphones using head related transfer functions (any order,
+  given HRIRs for enough virtual speakers)
+- Speakers: playback over arbitrary loudspeaker layouts, with presets for quad, 5.1, 7.1, 7.1.4,
+  and regular rings or domes (any order)
 
-Although at the moment only stereo output is supported, the *B-format* abstraction should make
-it easy to implement arbitrary speaker configurations in the future.
+Custom decoders can be plugged in by implementing the `Renderer` trait.
+
+Loudspeaker playback produces one channel per speaker. The audio device's default configuration
+should provide that many channels; otherwise `rodio` drops or duplicates channels to match.
+
+Controllers, listeners, and the scene pass their changes to the audio thread through queues,
+which the audio thread reads without waiting or allocating. Sending is cheap while the audio
+thread keeps up, but it is not wait-free: when changes pile up faster than they are rendered,
+the sending thread locks an overflow list and allocates room in it, so it can block on other
+sending threads and allocate.
 */
 
+mod absorption;
+#[cfg(feature = "wav")]
+mod ambix;
 mod bformat;
 mod bmixer;
 mod bstream;
+mod bus;
+mod convolution;
+mod decoder;
+mod delay;
+mod directivity;
+mod distance;
+mod error;
+mod linalg;
+mod listener;
+mod nearfield;
+mod occlusion;
+mod offline;
+mod queue;
 mod renderer;
+mod resampler;
+mod reverb;
+mod room;
+mod rotation;
+#[cfg(feature = "sofa")]
+mod sofa;
+mod speakers;
+mod sphere;
+mod spread;
 
 pub mod constants;
 pub mod sources;
-pub use bmixer::{bmixer, BmixerComposer, BstreamMixer};
-pub use bstream::{bstream, Bstream, BstreamConfig, SoundController};
-pub use renderer::{BstreamHrtfRenderer, BstreamStereoRenderer, HrtfConfig, StereoConfig};
+pub use absorption::AirAbsorption;
+#[cfg(feature = "wav")]
+pub use ambix::{AmbixReader, AmbixWriter};
+pub use bformat::{
+    AmbisonicOrder, Bformat, BformatFrames, BformatSource, Bweights, MAX_CHANNELS, MAX_ORDER,
+};
+pub use bmixer::{bmixer, bmixer_with_order, BmixerComposer, BstreamMixer};
+pub use bstream::{bfield, bstream, Bfield, Bstream, BstreamConfig, SoundController};
+pub use bus::{BusController, Effect};
+pub use decoder::DecoderMethod;
+pub use directivity::Directivity;
+pub use distance::DistanceModel;
+pub use error::Error;
+pub use listener::Listener;
+pub use offline::OfflineAmbisonic;
+pub use renderer::{
+    BstreamHrtfRenderer, BstreamRenderer, BstreamStereoRenderer, HrtfConfig, Renderer, StereoConfig,
+};
+pub use resampler::Interpolation;
+pub use reverb::ReverbConfig;
 pub use rodio;
+pub use room::Room;
+pub use rotation::Orientation;
+pub use speakers::{BstreamSpeakerRenderer, SpeakerConfig};
+pub use spread::Spread;
 
 use std::f32;
 use std::sync::Arc;
@@ -73,6 +143,54 @@ pub enum PlaybackConfiguration {
 
     /// Headphone playback using head related transfer functions
     Hrtf(HrtfConfig),
+
+    /// Playback over a loudspeaker layout
+    Speakers(SpeakerConfig),
+
+    /// Playback with a custom renderer
+    Custom(Box<dyn Renderer + Send>),
+}
+
+impl PlaybackConfiguration {
+    /// Play back using a custom renderer
+    pub fn custom<R: Renderer + Send + 'static>(renderer: R) -> Self {
+        PlaybackConfiguration::Custom(Box::new(renderer))
+    }
+
+    /// Check that the configuration can render a mix at `sample_rate`
+    fn check_sample_rate(&self, sample_rate: u32) -> Result<(), Error> {
+        match self {
+            PlaybackConfiguration::Hrtf(cfg) if cfg.sample_rate != sample_rate => {
+                Err(Error::SampleRateMismatch {
+                    expected: sample_rate,
+                    found: cfg.sample_rate,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Construct the renderer that decodes the mixer's output for this configuration
+    fn into_renderer(self, mixer: BstreamMixer) -> Box<dyn rodio::Source<Item = f32> + Send> {
+        match self {
+            PlaybackConfiguration::Stereo(cfg) => {
+                Box::new(renderer::BstreamStereoRenderer::new(mixer, cfg))
+            }
+
+            PlaybackConfiguration::Hrtf(cfg) => {
+                Box::new(renderer::BstreamHrtfRenderer::new(mixer, cfg))
+            }
+
+            PlaybackConfiguration::Speakers(cfg) => {
+                let order = mixer.order();
+                Box::new(speakers::BstreamSpeakerRenderer::new(mixer, cfg, order))
+            }
+
+            PlaybackConfiguration::Custom(renderer) => {
+                Box::new(renderer::BstreamRenderer::new(mixer, renderer))
+            }
+        }
+    }
 }
 
 impl Default for PlaybackConfiguration {
@@ -93,10 +211,17 @@ impl From<HrtfConfig> for PlaybackConfiguration {
     }
 }
 
+impl From<SpeakerConfig> for PlaybackConfiguration {
+    fn from(cfg: SpeakerConfig) -> Self {
+        PlaybackConfiguration::Speakers(cfg)
+    }
+}
+
 /// A builder object for creating `Ambisonic` contexts
 pub struct AmbisonicBuilder {
     device: Option<rodio::Device>,
     sample_rate: u32,
+    order: AmbisonicOrder,
     config: PlaybackConfiguration,
 }
 
@@ -107,34 +232,46 @@ impl AmbisonicBuilder {
     }
 
     /// Build the ambisonic context
+    ///
+    /// Panics if the audio device cannot be opened, or if the playback configuration does not
+    /// match the sample rate. Use `try_build` to handle these cases.
     pub fn build(self) -> Ambisonic {
+        self.try_build()
+            .expect("Cannot open audio device for ambisonic playback")
+    }
+
+    /// Build the ambisonic context, or return an error if the audio device cannot be opened
+    ///
+    /// Fails with `Error::SampleRateMismatch` if an HRTF configuration was loaded for another
+    /// sample rate than that of the mix.
+    pub fn try_build(self) -> Result<Ambisonic, Error> {
+        self.config.check_sample_rate(self.sample_rate)?;
+
         let (stream, stream_handle) = if let Some(device) = self.device {
-            rodio::OutputStream::try_from_device(&device).unwrap()
+            rodio::OutputStream::try_from_device(&device)?
         } else {
-            rodio::OutputStream::try_default().unwrap()
+            rodio::OutputStream::try_default()?
         };
 
-        let sink = rodio::Sink::try_new(&stream_handle).unwrap();
-
-        let (mixer, controller) = bmixer::bmixer(self.sample_rate);
+        let sink = rodio::Sink::try_new(&stream_handle)?;
 
-        match self.config {
-            PlaybackConfiguration::Stereo(cfg) => {
-                let output = renderer::BstreamStereoRenderer::new(mixer, cfg);
-                sink.append(output);
-            }
+        let (mixer, controller) = bmixer::bmixer_with_order(self.sample_rate, self.order);
+        sink.append(self.config.into_renderer(mixer));
 
-            PlaybackConfiguration::Hrtf(cfg) => {
-                let output = renderer::BstreamHrtfRenderer::new(mixer, cfg);
-                sink.append(output);
-            }
-        }
-
-        Ambisonic {
+        Ok(Ambisonic {
             sink,
             output_stream: stream,
             composer: controller,
-        }
+        })
+    }
+
+    /// Build an ambisonic context that renders into memory instead of a sound card
+    ///
+    /// The selected device is ignored. Panics if the playback configuration does not match the
+    /// sample rate.
+    pub fn build_offline(self) -> OfflineAmbisonic {
+        let (mixer, controller) = bmixer::bmixer_with_order(self.sample_rate, self.order);
+        OfflineAmbisonic::new(self.config.into_renderer(mixer), controller)
     }
 
     /// Select device (defaults to `rodio::default_output_device()`
@@ -153,10 +290,22 @@ impl AmbisonicBuilder {
         }
     }
 
+    /// Set ambisonic order of the mix (defaults to first order)
+    ///
+    /// Higher orders only improve localisation if the playback configuration supports them.
+    pub fn with_order(self, order: AmbisonicOrder) -> Self {
+        AmbisonicBuilder { order, ..self }
+    }
+
     /// Set playback configuration
     pub fn with_config(self, config: PlaybackConfiguration) -> Self {
         AmbisonicBuilder { config, ..self }
     }
+
+    /// Play back using a custom renderer
+    pub fn with_renderer<R: Renderer + Send + 'static>(self, renderer: R) -> Self {
+        self.with_config(PlaybackConfiguration::custom(renderer))
+    }
 }
 
 impl Default for AmbisonicBuilder {
@@ -164,6 +313,7 @@ impl Default for AmbisonicBuilder {
         AmbisonicBuilder {
             device: None,
             sample_rate: 48000,
+            order: AmbisonicOrder::default(),
             config: PlaybackConfiguration::default(),
         }
     }
@@ -220,4 +370,106 @@ impl Ambisonic {
         self.composer
             .play(input, BstreamConfig::new().with_position(pos))
     }
+
+    /// Add a single-channel `Source` to the sound scene with the given configuration.
+    ///
+    /// Returns a controller object that can be used to control the source during playback.
+    #[inline(always)]
+    pub fn play_with_config<I>(&self, input: I, config: BstreamConfig) -> SoundController
+    where
+        I: rodio::Source<Item = f32> + Send + 'static,
+    {
+        self.composer.play(input, config)
+    }
+
+    /// Add a *B-format* `Source` to the sound scene, such as an `AmbixReader`.
+    ///
+    /// Returns a controller object that can be used to pause or stop the sound field.
+    #[inline(always)]
+    pub fn play_bformat<I>(&self, input: I) -> SoundController
+    where
+        I: rodio::Source<Item = f32> + Send + 'static,
+    {
+        self.composer.play_bformat(input)
+    }
+
+    /// Add a *B-format* `Source` to the sound scene with the given configuration.
+    ///
+    /// Only the gain and bus of the configuration apply to sound fields.
+    #[inline(always)]
+    pub fn play_bformat_with_config<I>(&self, input: I, config: BstreamConfig) -> SoundController
+    where
+        I: rodio::Source<Item = f32> + Send + 'static,
+    {
+        self.composer.play_bformat_with_config(input, config)
+    }
+
+    /// Get the controller of a named bus, creating the bus if necessary
+    ///
+    /// Assign sources to the bus with `BstreamConfig::with_bus`.
+    pub fn bus(&self, name: &str) -> BusController {
+        self.composer.bus(name)
+    }
+
+    /// Get the controller of the master bus, which receives all sources and buses
+    pub fn master(&self) -> BusController {
+        self.composer.master()
+    }
+
+    /// Set the reverb of the scene, or disable it
+    ///
+    /// Each source contributes to the reverb according to its send level (see
+    /// `SoundController::set_reverb_send`).
+    pub fn set_reverb(&self, config: Option<ReverbConfig>) {
+        self.composer.set_reverb(config);
+    }
+
+    /// Set the room that produces early reflections of all sources, or remove it
+    ///
+    /// Use `ReverbConfig::from_room` to set a matching reverb.
+    pub fn set_room(&self, room: Option<Room>) {
+        self.composer.set_room(room);
+    }
+
+    /// Set the master gain
+    ///
+    /// The gain changes over a few milliseconds to avoid clicks.
+    pub fn set_master_gain(&self, gain: f32) {
+        self.composer.master().set_gain(gain);
+    }
+
+    /// Set the orientation of the listener
+    ///
+    /// Source positions remain relative to the listener's position, but are no longer relative
+    /// to the direction the listener is facing. The sound field transitions smoothly to the new
+    /// orientation.
+    pub fn set_listener_orientation(&self, orientation: Orientation) {
+        self.composer.set_listener_orientation(orientation);
+    }
+
+    /// Get a controller of the listener of world-space sources
+    pub fn listener(&self) -> Listener {
+        self.composer.listener()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hrtfs_of_another_sample_rate_are_rejected() {
+        let result = AmbisonicBuilder::new()
+            .with_sample_rate(44100)
+            .with_config(HrtfConfig::default().into())
+            .try_build();
+
+        assert!(matches!(
+            result,
+            Err(Error::SampleRateMismatch {
+                expected: 44100,
+                found: 48000
+            })
+        ));
+    }
 }
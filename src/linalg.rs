@@ -0,0 +1,139 @@
+//! Small dense linear algebra helpers
+//!
+//! Matrices are stored as vectors of rows. Computations are done in `f64` because decoder and
+//! rotation matrices are computed rarely, but need to be accurate.
+
+/// Dense matrix stored as a vector of rows
+pub type Matrix = Vec<Vec<f64>>;
+
+/// Transpose a matrix
+pub fn transpose(a: &[Vec<f64>]) -> Matrix {
+    let cols = a.first().map(Vec::len).unwrap_or(0);
+    (0..cols)
+        .map(|j| a.iter().map(|row| row[j]).collect())
+        .collect()
+}
+
+/// Multiply two matrices
+pub fn matmul(a: &[Vec<f64>], b: &[Vec<f64>]) -> Matrix {
+    let cols = b.first().map(Vec::len).unwrap_or(0);
+    a.iter()
+        .map(|row| {
+            (0..cols)
+                .map(|j| row.iter().zip(b).map(|(x, brow)| x * brow[j]).sum())
+                .collect()
+        })
+        .collect()
+}
+
+/// Invert a square matrix using Gauss-Jordan elimination with partial pivoting.
+///
+/// Returns `None` if the matrix is singular.
+pub fn invert(a: &[Vec<f64>]) -> Option<Matrix> {
+    let n = a.len();
+    let mut m: Matrix = a.to_vec();
+    let mut inv: Matrix = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+
+    let scale = a
+        .iter()
+        .flat_map(|row| row.iter())
+        .fold(0.0f64, |acc, x| acc.max(x.abs()));
+
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| m[i][col].abs().total_cmp(&m[j][col].abs()))?;
+        if m[pivot][col].abs() <= scale * 1e-12 {
+            return None;
+        }
+        m.swap(col, pivot);
+        inv.swap(col, pivot);
+
+        let p = m[col][col];
+        for j in 0..n {
+            m[col][j] /= p;
+            inv[col][j] /= p;
+        }
+
+        for i in 0..n {
+            if i == col {
+                continue;
+            }
+            let f = m[i][col];
+            if f == 0.0 {
+                continue;
+            }
+            for j in 0..n {
+                m[i][j] -= f * m[col][j];
+                inv[i][j] -= f * inv[col][j];
+            }
+        }
+    }
+
+    Some(inv)
+}
+
+/// Compute the Moore-Penrose pseudo-inverse of a matrix with full rank.
+///
+/// Returns `None` if the matrix is rank deficient.
+pub fn pseudo_inverse(a: &[Vec<f64>]) -> Option<Matrix> {
+    let at = transpose(a);
+    if a.len() >= at.len() {
+        // more rows than columns: (A'A)^-1 A'
+        Some(matmul(&invert(&matmul(&at, a))?, &at))
+    } else {
+        // more columns than rows: A' (AA')^-1
+        Some(matmul(&at, &invert(&matmul(a, &at))?))
+    }
+}
+
+/// Compute the regularized pseudo-inverse `A' (AA' + λI)^-1`, which exists for any matrix.
+///
+/// Directions in which `A` has singular values much smaller than `sqrt(λ)` are suppressed,
+/// so rank deficient matrices result in the minimum norm solution.
+pub fn regularized_pseudo_inverse(a: &[Vec<f64>], lambda: f64) -> Matrix {
+    let at = transpose(a);
+    let mut aat = matmul(a, &at);
+    for (i, row) in aat.iter_mut().enumerate() {
+        row[i] += lambda;
+    }
+    matmul(
+        &at,
+        &invert(&aat).expect("regularized matrix is positive definite"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pseudo_inverse_of_wide_matrix_is_right_inverse() {
+        let a = vec![vec![1.0, 2.0, 3.0], vec![0.0, 1.0, -1.0]];
+        let product = matmul(&a, &pseudo_inverse(&a).unwrap());
+        for (i, row) in product.iter().enumerate() {
+            for (j, x) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((x - expected).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn regularized_pseudo_inverse_handles_rank_deficiency() {
+        let a = vec![vec![1.0, 2.0], vec![2.0, 4.0], vec![0.0, 0.0]];
+        let b = regularized_pseudo_inverse(&a, 1e-9);
+        let aba = matmul(&matmul(&a, &b), &a);
+        for (row, expected) in aba.iter().zip(&a) {
+            for (x, e) in row.iter().zip(expected) {
+                assert!((x - e).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn singular_matrices_cannot_be_inverted() {
+        let a = vec![vec![1.0, 2.0], vec![2.0, 4.0]];
+        assert!(invert(&a).is_none());
+    }
+}
@@ -0,0 +1,66 @@
+//! Listener of world-space sources
+
+use std::sync::Arc;
+
+use crate::bmixer::BmixerComposer;
+use crate::rotation::Orientation;
+
+/// Position and velocity of the listener in world space
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub(crate) struct ListenerState {
+    pub(crate) position: [f32; 3],
+    pub(crate) velocity: [f32; 3],
+}
+
+/// Controls the listener of a sound scene
+///
+/// Sources in world space (see `BstreamConfig::with_world_space`) are heard relative to the
+/// listener's position and velocity, which the scene takes into account for their direction,
+/// distance attenuation, and Doppler effect. Other sources remain relative to the listener.
+/// The orientation applies to all sources.
+///
+/// Listeners can be cloned; all clones control the same listener.
+#[derive(Clone)]
+pub struct Listener {
+    composer: Arc<BmixerComposer>,
+}
+
+impl Listener {
+    pub(crate) fn new(composer: Arc<BmixerComposer>) -> Self {
+        Listener { composer }
+    }
+
+    /// Move the listener to a position in world space
+    ///
+    /// World-space sources transition smoothly to their new relative positions.
+    pub fn set_position(&self, position: [f32; 3]) {
+        self.composer
+            .update_listener(|listener| listener.position = position);
+    }
+
+    /// Set the listener's velocity in world space
+    ///
+    /// The velocity determines the Doppler effect of world-space sources but has no effect on
+    /// the listener's position.
+    pub fn set_velocity(&self, velocity: [f32; 3]) {
+        self.composer
+            .update_listener(|listener| listener.velocity = velocity);
+    }
+
+    /// Set the orientation of the listener
+    ///
+    /// See `BmixerComposer::set_listener_orientation`.
+    pub fn set_orientation(&self, orientation: Orientation) {
+        self.composer.set_listener_orientation(orientation);
+    }
+
+    /// Position of the listener, as last set
+    pub fn position(&self) -> [f32; 3] {
+        self.composer.listener_state().position
+    }
+
+    /// Velocity of the listener, as last set
+    pub fn velocity(&self) -> [f32; 3] {
+        self.composer.listener_state().velocity
+    }
+}
@@ -0,0 +1,172 @@
+//! Near-field compensation of close sources
+//!
+//! *B-format* encodes sources as plane waves. The sound field of a close point source has
+//! curved wave fronts, which boost the low frequencies of the directional components: the closer
+//! the source and the higher the order, the stronger the boost. Near-field compensation (NFC)
+//! restores this by filtering each order of a source with the ratio of the near-field responses
+//! at the source's distance and at a reference radius, e.g. that of the loudspeakers.
+
+use crate::bformat::{Bformat, Bweights, COMPONENT_DEGREES, MAX_CHANNELS, MAX_ORDER};
+
+/// Factors of the reverse Bessel polynomials of degree 1 to `MAX_ORDER`, as `(s^2, s, 1)`
+/// coefficients of real first- and second-order sections
+const BESSEL_SECTIONS: [&[[f32; 3]]; MAX_ORDER] = [
+    &[[0.0, 1.0, 1.0]],
+    &[[1.0, 3.0, 3.0]],
+    &[[0.0, 1.0, 2.322_185_4], [1.0, 3.677_814_6, 6.459_432_7]],
+];
+
+/// Sources closer than this fraction of the reference radius are compensated as if they were
+/// at this distance, which limits the boost of low frequencies
+const MIN_RELATIVE_DISTANCE: f32 = 0.25;
+
+/// Second-order IIR filter section
+#[derive(Default, Copy, Clone)]
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    state: [f32; 2],
+}
+
+impl Biquad {
+    /// Discretize the analog filter `(s^2 n0 + s n1 + n2) / (s^2 d0 + s d1 + d2)` with the
+    /// bilinear transform
+    fn set_analog(&mut self, n: [f32; 3], d: [f32; 3], sample_rate: f32) {
+        let k = 2.0 * sample_rate;
+        let kk = k * k;
+        let discretize = |p: [f32; 3]| {
+            [
+                p[0] * kk + p[1] * k + p[2],
+                2.0 * (p[2] - p[0] * kk),
+                p[0] * kk - p[1] * k + p[2],
+            ]
+        };
+
+        let b = discretize(n);
+        let a = discretize(d);
+        self.b = [b[0] / a[0], b[1] / a[0], b[2] / a[0]];
+        self.a = [a[1] / a[0], a[2] / a[0]];
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        // transposed direct form II
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// Near-field compensation filters of a single source
+pub(crate) struct NearField {
+    radius: f32,
+    sample_rate: f32,
+    speed_of_sound: f32,
+    /// Filter sections of each order above zero
+    sections: [[Biquad; 2]; MAX_ORDER],
+}
+
+impl NearField {
+    /// Construct filters that compensate sources closer than `radius`
+    pub(crate) fn new(radius: f32, sample_rate: u32, speed_of_sound: f32) -> Self {
+        let mut near_field = NearField {
+            radius,
+            sample_rate: sample_rate as f32,
+            speed_of_sound,
+            sections: Default::default(),
+        };
+        near_field.set_distance(radius);
+        near_field
+    }
+
+    /// Adapt the filters to a source at given distance
+    ///
+    /// Sources at or beyond the reference radius pass unchanged.
+    pub(crate) fn set_distance(&mut self, distance: f32) {
+        let distance = distance.clamp(MIN_RELATIVE_DISTANCE * self.radius, self.radius);
+        let near = self.speed_of_sound / distance;
+        let reference = self.speed_of_sound / self.radius;
+
+        for (sections, factors) in self.sections.iter_mut().zip(BESSEL_SECTIONS) {
+            for (section, f) in sections.iter_mut().zip(factors) {
+                // substitute s / w for s in the polynomial and normalize its leading coefficient
+                let scaled = |w: f32| {
+                    if f[0] == 0.0 {
+                        [0.0, f[1], f[2] * w]
+                    } else {
+                        [f[0], f[1] * w, f[2] * w * w]
+                    }
+                };
+                section.set_analog(scaled(near), scaled(reference), self.sample_rate);
+            }
+        }
+    }
+
+    /// Filter the next sample of the source and encode it with `weights`
+    pub(crate) fn process(&mut self, x: f32, weights: &Bweights) -> Bformat {
+        let mut signals = [x; MAX_ORDER + 1];
+        for (order, sections) in self.sections.iter_mut().enumerate() {
+            let mut y = x;
+            for section in &mut sections[..BESSEL_SECTIONS[order].len()] {
+                y = section.process(y);
+            }
+            signals[order + 1] = y;
+        }
+
+        let mut components = [0.0; MAX_CHANNELS];
+        for ((c, w), &degree) in components
+            .iter_mut()
+            .zip(weights.components())
+            .zip(&COMPONENT_DEGREES)
+        {
+            *c = w * signals[degree];
+        }
+        Bformat::from_components(components)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steady-state gain of each order for a constant input
+    fn dc_gains(near_field: &mut NearField) -> [f32; MAX_ORDER + 1] {
+        let weights = Bweights::from_components([1.0; MAX_CHANNELS]);
+        let mut output = Bformat::zero();
+        for _ in 0..48000 {
+            output = near_field.process(1.0, &weights);
+        }
+        let c = output.components();
+        [c[0], c[1], c[4], c[9]]
+    }
+
+    #[test]
+    fn close_sources_boost_low_frequencies_by_order() {
+        let mut near_field = NearField::new(1.0, 48000, 343.0);
+        near_field.set_distance(0.5);
+
+        let gains = dc_gains(&mut near_field);
+        for (n, gain) in gains.iter().enumerate() {
+            assert!(
+                (gain - 2f32.powi(n as i32)).abs() < 1e-2 * gain,
+                "{:?}",
+                gains
+            );
+        }
+    }
+
+    #[test]
+    fn high_frequencies_and_distant_sources_are_unchanged() {
+        let mut near_field = NearField::new(1.0, 48000, 343.0);
+        near_field.set_distance(0.5);
+        let weights = Bweights::from_components([1.0; MAX_CHANNELS]);
+        let output: Vec<Bformat> = (0..1000)
+            .map(|i| near_field.process(if i % 2 == 0 { 1.0 } else { -1.0 }, &weights))
+            .collect();
+        assert!((output[999].components()[9] + 1.0).abs() < 1e-2);
+
+        near_field.set_distance(2.0);
+        let gains = dc_gains(&mut near_field);
+        assert!(gains.iter().all(|g| (g - 1.0).abs() < 1e-3), "{:?}", gains);
+    }
+}
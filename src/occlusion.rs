@@ -0,0 +1,69 @@
+//! Muffling of sources behind obstacles
+
+use crate::absorption::Lowpass;
+
+/// Gain of a fully occluded or obstructed path
+const BLOCKED_GAIN: f32 = 0.1;
+
+/// Cutoff frequency in Hz of a fully occluded or obstructed path
+const BLOCKED_CUTOFF: f32 = 500.0;
+
+/// Cutoff frequency in Hz at which the filter starts to close
+const OPEN_CUTOFF: f32 = 20000.0;
+
+/// Fraction of the remaining gain change applied per sample
+const SMOOTHING: f32 = 0.001;
+
+/// Gain reduction and low-pass filter for a sound path through an obstacle
+///
+/// The amount ranges from 0 (no obstacle) to 1 (fully blocked). Both gain and cutoff frequency
+/// fall exponentially with the amount and follow changes smoothly.
+pub(crate) struct Occlusion {
+    gain: f32,
+    target_gain: f32,
+    lowpass: Lowpass,
+}
+
+impl Occlusion {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Occlusion {
+            gain: 1.0,
+            target_gain: 1.0,
+            lowpass: Lowpass::new(None, sample_rate),
+        }
+    }
+
+    pub(crate) fn set_amount(&mut self, amount: f32) {
+        let amount = amount.clamp(0.0, 1.0);
+        self.target_gain = BLOCKED_GAIN.powf(amount);
+        self.lowpass.set_cutoff(if amount > 0.0 {
+            Some(OPEN_CUTOFF * (BLOCKED_CUTOFF / OPEN_CUTOFF).powf(amount))
+        } else {
+            None
+        });
+    }
+
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        self.gain += (self.target_gain - self.gain) * SMOOTHING;
+        self.lowpass.process(x) * self.gain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocked_paths_are_quiet_and_dull() {
+        let mut occlusion = Occlusion::new(48000);
+        occlusion.set_amount(1.0);
+
+        let constant: Vec<f32> = (0..48000).map(|_| occlusion.process(1.0)).collect();
+        assert!((constant[47999] - BLOCKED_GAIN).abs() < 1e-4);
+
+        let alternating: Vec<f32> = (0..1000)
+            .map(|i| occlusion.process(if i % 2 == 0 { 1.0 } else { -1.0 }))
+            .collect();
+        assert!(alternating[500..].iter().all(|x| x.abs() < 1e-3));
+    }
+}
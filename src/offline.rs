@@ -0,0 +1,380 @@
+//! Render sound scenes without an audio device
+//!
+//! This module provides an ambisonic context that renders the sound scene into sample buffers,
+//! for example to pre-render audio or to test scenes on machines without a sound card.
+
+use crate::bmixer::BmixerComposer;
+use crate::bstream::{BstreamConfig, SoundController};
+use crate::bus::BusController;
+use crate::listener::Listener;
+use crate::reverb::ReverbConfig;
+use crate::room::Room;
+use crate::rotation::Orientation;
+use rodio::Source;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Ambisonic context that renders into memory.
+///
+/// Sounds are added with the same API as in `Ambisonic`, but time only advances when output is
+/// requested with one of the `render_*` methods. Rendering is deterministic, provided the sources
+/// are.
+pub struct OfflineAmbisonic {
+    output: Box<dyn Source<Item = f32> + Send>,
+    composer: Arc<BmixerComposer>,
+}
+
+impl OfflineAmbisonic {
+    pub(crate) fn new(
+        output: Box<dyn Source<Item = f32> + Send>,
+        composer: Arc<BmixerComposer>,
+    ) -> Self {
+        OfflineAmbisonic { output, composer }
+    }
+
+    /// Add a single-channel `Source` to the sound scene, initialized as omnidirectional.
+    ///
+    /// Returns a controller object that can be used to control the source during playback.
+    #[inline(always)]
+    pub fn play_omni<I>(&self, input: I) -> SoundController
+    where
+        I: Source<Item = f32> + Send + 'static,
+    {
+        self.composer.play(input, BstreamConfig::new())
+    }
+
+    /// Add a single-channel `Source` to the sound scene at a position relative to the listener.
+    ///
+    /// Returns a controller object that can be used to control the source during playback.
+    #[inline(always)]
+    pub fn play_at<I>(&self, input: I, pos: [f32; 3]) -> SoundController
+    where
+        I: Source<Item = f32> + Send + 'static,
+    {
+        self.composer
+            .play(input, BstreamConfig::new().with_position(pos))
+    }
+
+    /// Add a single-channel `Source` to the sound scene with the given configuration.
+    ///
+    /// Returns a controller object that can be used to control the source during playback.
+    #[inline(always)]
+    pub fn play_with_config<I>(&self, input: I, config: BstreamConfig) -> SoundController
+    where
+        I: Source<Item = f32> + Send + 'static,
+    {
+        self.composer.play(input, config)
+    }
+
+    /// Add a *B-format* `Source` to the sound scene.
+    ///
+    /// See `Ambisonic::play_bformat`.
+    #[inline(always)]
+    pub fn play_bformat<I>(&self, input: I) -> SoundController
+    where
+        I: Source<Item = f32> + Send + 'static,
+    {
+        self.composer.play_bformat(input)
+    }
+
+    /// Add a *B-format* `Source` to the sound scene with the given configuration.
+    ///
+    /// See `Ambisonic::play_bformat_with_config`.
+    #[inline(always)]
+    pub fn play_bformat_with_config<I>(&self, input: I, config: BstreamConfig) -> SoundController
+    where
+        I: Source<Item = f32> + Send + 'static,
+    {
+        self.composer.play_bformat_with_config(input, config)
+    }
+
+    /// Get the controller of a named bus
+    ///
+    /// See `Ambisonic::bus`.
+    pub fn bus(&self, name: &str) -> BusController {
+        self.composer.bus(name)
+    }
+
+    /// Get the controller of the master bus
+    pub fn master(&self) -> BusController {
+        self.composer.master()
+    }
+
+    /// Set the reverb of the scene, or disable it
+    ///
+    /// See `Ambisonic::set_reverb`.
+    pub fn set_reverb(&self, config: Option<ReverbConfig>) {
+        self.composer.set_reverb(config);
+    }
+
+    /// Set the room that produces early reflections of all sources, or remove it
+    ///
+    /// See `Ambisonic::set_room`.
+    pub fn set_room(&self, room: Option<Room>) {
+        self.composer.set_room(room);
+    }
+
+    /// Set the master gain
+    ///
+    /// See `Ambisonic::set_master_gain`.
+    pub fn set_master_gain(&self, gain: f32) {
+        self.composer.master().set_gain(gain);
+    }
+
+    /// Set the orientation of the listener
+    ///
+    /// See `Ambisonic::set_listener_orientation`.
+    pub fn set_listener_orientation(&self, orientation: Orientation) {
+        self.composer.set_listener_orientation(orientation);
+    }
+
+    /// Get a controller of the listener of world-space sources
+    pub fn listener(&self) -> Listener {
+        self.composer.listener()
+    }
+
+    /// Number of interleaved channels in the rendered output
+    pub fn channels(&self) -> u16 {
+        self.output.channels()
+    }
+
+    /// Sample rate of the rendered output
+    pub fn sample_rate(&self) -> u32 {
+        self.output.sample_rate()
+    }
+
+    /// Render the given number of frames.
+    ///
+    /// Returns interleaved samples, `channels()` per frame.
+    pub fn render_frames(&mut self, n_frames: usize) -> Vec<f32> {
+        let mut buffer = vec![0.0; n_frames * self.channels() as usize];
+        self.render_into(&mut buffer);
+        buffer
+    }
+
+    /// Render the given duration.
+    ///
+    /// The duration is rounded down to whole frames. Returns interleaved samples, `channels()`
+    /// per frame.
+    pub fn render(&mut self, duration: Duration) -> Vec<f32> {
+        let n_frames = duration.as_secs_f64() * self.sample_rate() as f64;
+        self.render_frames(n_frames as usize)
+    }
+
+    /// Fill a buffer with interleaved samples.
+    ///
+    /// The buffer's length should be a multiple of `channels()`.
+    pub fn render_into(&mut self, buffer: &mut [f32]) {
+        for (out, x) in buffer.iter_mut().zip(&mut self.output) {
+            *out = x;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OfflineAmbisonic;
+    use crate::sources::Constant;
+    use crate::{
+        AmbisonicBuilder, Bformat, BstreamConfig, Orientation, Renderer, ReverbConfig, Room,
+    };
+    use rodio::Source;
+    use std::time::Duration;
+
+    #[test]
+    fn empty_scene_renders_silence() {
+        let mut scene = AmbisonicBuilder::new().build_offline();
+
+        let output = scene.render_frames(100);
+
+        assert_eq!(output.len(), 200);
+        assert!(output.iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn duration_is_converted_to_frames() {
+        let mut scene = AmbisonicBuilder::new()
+            .with_sample_rate(1000)
+            .build_offline();
+
+        let output = scene.render(Duration::from_millis(250));
+
+        assert_eq!(output.len(), 250 * scene.channels() as usize);
+    }
+
+    #[test]
+    fn custom_renderers_decode_the_mix() {
+        struct Omni;
+
+        impl Renderer for Omni {
+            fn channels(&self) -> u16 {
+                3
+            }
+
+            fn render(&mut self, input: &Bformat, output: &mut [f32]) {
+                output.iter_mut().for_each(|x| *x = input.w());
+            }
+        }
+
+        let mut scene = AmbisonicBuilder::new().with_renderer(Omni).build_offline();
+        let _sound = scene.play_omni(Constant::new(1.0, 48000));
+
+        assert_eq!(scene.channels(), 3);
+        let output = scene.render_frames(10);
+        assert_eq!(output.len(), 30);
+        assert!(output.iter().all(|&x| x == output[0] && x > 0.0));
+    }
+
+    #[test]
+    fn more_sources_than_reserved_are_mixed() {
+        let mut scene = AmbisonicBuilder::new()
+            .with_sample_rate(1000)
+            .build_offline();
+        let single = {
+            let _sound = scene.play_omni(Constant::new(1.0, 1000));
+            scene.render_frames(1)[0]
+        };
+
+        let n = 2 * crate::bus::STREAM_CAPACITY + 1;
+        let _sounds: Vec<_> = (0..n)
+            .map(|_| scene.play_omni(Constant::new(1.0, 1000)))
+            .collect();
+        let output = scene.render_frames(1)[0];
+        assert!((output - (n + 1) as f32 * single).abs() < 1e-3 * output);
+    }
+
+    #[test]
+    fn sources_on_the_right_are_louder_on_the_right_channel() {
+        let mut scene = AmbisonicBuilder::new().build_offline();
+        let _sound = scene.play_at(Constant::new(1.0, 48000), [1.0, 0.0, 0.0]);
+
+        let output = scene.render_frames(10);
+
+        for frame in output.chunks(2) {
+            assert!(frame[1] > frame[0]);
+        }
+    }
+
+    #[test]
+    fn turning_left_moves_sources_in_front_to_the_right() {
+        let mut scene = AmbisonicBuilder::new().build_offline();
+        let _sound = scene.play_at(Constant::new(1.0, 48000), [0.0, 1.0, 0.0]);
+        scene.set_listener_orientation(Orientation::from_yaw_pitch_roll(
+            std::f32::consts::FRAC_PI_2,
+            0.0,
+            0.0,
+        ));
+
+        let output = scene.render(Duration::from_millis(50));
+
+        let last = &output[output.len() - 2..];
+        assert!(last[1] > last[0]);
+    }
+
+    #[test]
+    fn world_space_sources_stay_in_place_when_the_listener_moves() {
+        let mut scene = AmbisonicBuilder::new().build_offline();
+        let listener = scene.listener();
+        listener.set_position([4.0, 0.0, 0.0]);
+        let config = BstreamConfig::new()
+            .with_position([5.0, 0.0, 0.0])
+            .with_world_space(true);
+        let _sound = scene.play_with_config(Constant::new(1.0, 48000), config);
+
+        let output = scene.render_frames(10);
+        assert!(output.chunks(2).all(|frame| frame[1] > frame[0]));
+
+        listener.set_position([6.0, 0.0, 0.0]);
+        let output = scene.render(Duration::from_millis(50));
+
+        let last = &output[output.len() - 2..];
+        assert!(last[0] > last[1]);
+        assert_eq!(listener.position(), [6.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn paused_buses_leave_other_sources_playing() {
+        let mut scene = AmbisonicBuilder::new().build_offline();
+        let config = BstreamConfig::new()
+            .with_position([1.0, 0.0, 0.0])
+            .with_bus("sfx");
+        let _sfx = scene.play_with_config(Constant::new(1.0, 48000), config);
+        let _ui = scene.play_at(Constant::new(1.0, 48000), [0.0, 1.0, 0.0]);
+
+        let output = scene.render_frames(10);
+        assert!(output[1] > output[0]);
+
+        // the bus fades out before it pauses
+        scene.bus("sfx").pause();
+        let output = scene.render(Duration::from_millis(10));
+        let last = &output[output.len() - 2..];
+        assert!(last[0] > 0.0);
+        assert!((last[0] - last[1]).abs() < 1e-6);
+
+        scene.set_master_gain(0.0);
+        let output = scene.render(Duration::from_millis(10));
+        assert_eq!(output[output.len() - 1], 0.0);
+    }
+
+    #[test]
+    fn rooms_reflect_playing_and_new_sources() {
+        /// Last frame of a source that is added before or after the room is set
+        fn render(room: Option<Room>, played_before: bool) -> Vec<f32> {
+            let mut scene = AmbisonicBuilder::new().build_offline();
+            let play = |scene: &OfflineAmbisonic| {
+                let config = BstreamConfig::new().with_position([0.0, 1.0, 0.0]);
+                scene.play_with_config(Constant::new(1.0, 48000), config)
+            };
+
+            let _early = played_before.then(|| play(&scene));
+            scene.set_room(room);
+            let _late = (!played_before).then(|| play(&scene));
+
+            // the walls are 2 m from the listener, so all reflections arrive within 30 ms
+            let output = scene.render(Duration::from_millis(30));
+            output[output.len() - 2..].to_vec()
+        }
+
+        let room = Room::shoebox([4.0, 4.0, 4.0]);
+        let direct = render(None, true);
+        for &played_before in &[true, false] {
+            let reflected = render(Some(room), played_before);
+            assert!((reflected[0] - direct[0]).abs() > 1e-3);
+        }
+    }
+
+    #[test]
+    fn only_the_latest_scene_changes_apply() {
+        let render = |changes: &[f32]| {
+            let mut scene = AmbisonicBuilder::new().build_offline();
+            let config = BstreamConfig::new()
+                .with_position([0.0, 1.0, 0.0])
+                .with_world_space(true);
+            let _sound = scene.play_with_config(Constant::new(1.0, 48000), config);
+            for &x in changes {
+                scene.listener().set_position([x, 0.0, 0.0]);
+                scene.set_room(Some(Room::shoebox([4.0 + x, 4.0, 4.0])));
+            }
+            scene.render(Duration::from_millis(30))
+        };
+
+        assert_eq!(render(&[3.0, -1.0, 0.5]), render(&[0.5]));
+    }
+
+    #[test]
+    fn reverb_tails_follow_the_send_level() {
+        for &send in &[0.0, 1.0] {
+            let mut scene = AmbisonicBuilder::new().build_offline();
+            scene.set_reverb(Some(ReverbConfig::new()));
+            let config = BstreamConfig::new().with_reverb_send(send);
+            let _click = scene.play_with_config(
+                Constant::new(1.0, 48000).take_duration(Duration::from_millis(1)),
+                config,
+            );
+
+            let output = scene.render(Duration::from_millis(200));
+            let tail = &output[output.len() / 2..];
+            assert_eq!(tail.iter().any(|&x| x != 0.0), send > 0.0);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Queues from controllers to the audio thread
+//!
+//! Controllers send commands, and composers send new sources, through a bounded ring buffer
+//! with a single consumer and any number of producers. Producers claim slots with an atomic
+//! compare-and-swap, and the consumer only ever reads atomics, so the audio thread never waits
+//! for a controller thread and never allocates.
+//!
+//! Sending is not wait-free. When the ring is full, for example because an offline scene is not
+//! being rendered while its sources are updated, producers append to an overflow list instead.
+//! The overflow list is protected by a mutex, and it allocates as it grows, so under load a
+//! producer can block on other producers (or briefly on the consumer) and allocate. The consumer
+//! only tries to lock the mutex: if a producer holds it, the consumer picks up the overflow on a
+//! later call.
+//!
+//! In the other direction, the audio thread hands finished sources and replaced effects back to
+//! the control side with `try_send`, which never locks or allocates, so that they are not
+//! freed on the audio thread.
+
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Construct a queue with room for `capacity` values before overflowing
+///
+/// The capacity is rounded up to the next power of two.
+pub(crate) fn channel<T: Send>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let capacity = capacity.max(2).next_power_of_two();
+    let slots = (0..capacity)
+        .map(|i| Slot {
+            sequence: AtomicUsize::new(i),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        })
+        .collect();
+
+    let queue = Arc::new(Queue {
+        slots,
+        mask: capacity - 1,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        overflowed: AtomicBool::new(false),
+        overflow: Mutex::new(VecDeque::new()),
+    });
+
+    (
+        Sender {
+            queue: queue.clone(),
+        },
+        Receiver { queue },
+    )
+}
+
+/// Sending end of a queue
+///
+/// Senders can be cloned to send from several threads.
+pub(crate) struct Sender<T> {
+    queue: Arc<Queue<T>>,
+}
+
+/// Receiving end of a queue, which belongs to the audio thread
+pub(crate) struct Receiver<T> {
+    queue: Arc<Queue<T>>,
+}
+
+struct Slot<T> {
+    /// Position in the queue at which the slot can be written, or that plus one once the value
+    /// has been written
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct Queue<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    /// Position of the next value to receive, only advanced by the receiver
+    head: AtomicUsize,
+    /// Position of the next slot to claim
+    tail: AtomicUsize,
+    overflowed: AtomicBool,
+    overflow: Mutex<VecDeque<T>>,
+}
+
+// Values are moved between threads through the slots, and each slot is only accessed by the
+// thread that owns it according to its sequence number.
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Append a value to the queue
+    ///
+    /// Never fails. If the ring is full, the value goes to the overflow list, and so do all
+    /// further values until the receiver has caught up, which preserves their order. Appending
+    /// to the overflow list locks a mutex and may allocate; use `try_send` where that must not
+    /// happen.
+    pub(crate) fn send(&self, value: T) {
+        let value = if self.queue.overflowed.load(Ordering::Acquire) {
+            value
+        } else {
+            match self.queue.push(value) {
+                Ok(()) => return,
+                Err(value) => value,
+            }
+        };
+
+        let mut overflow = self.queue.overflow.lock().expect("Cannot lock overflow");
+        overflow.push_back(value);
+        self.queue.overflowed.store(true, Ordering::Release);
+    }
+
+    /// Append a value to the ring without locking or allocating
+    ///
+    /// Returns the value if the ring is full, or if earlier values are waiting in the overflow
+    /// list.
+    pub(crate) fn try_send(&self, value: T) -> Result<(), T> {
+        if self.queue.overflowed.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        self.queue.push(value)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Take the next value from the queue, if there is one
+    ///
+    /// Does not block or allocate. Values that are being written concurrently may only be
+    /// received by a later call.
+    pub(crate) fn recv(&mut self) -> Option<T> {
+        if let Some(value) = self.queue.pop() {
+            return Some(value);
+        }
+
+        // the overflow holds values sent after those in the ring, so wait until the ring is
+        // empty, including slots that have been claimed but not yet written
+        let queue = &self.queue;
+        if !queue.overflowed.load(Ordering::Acquire)
+            || queue.tail.load(Ordering::Acquire) != queue.head.load(Ordering::Relaxed)
+        {
+            return None;
+        }
+
+        let mut overflow = queue.overflow.try_lock().ok()?;
+        let value = overflow.pop_front();
+        if overflow.is_empty() {
+            queue.overflowed.store(false, Ordering::Release);
+        }
+        value
+    }
+}
+
+impl<T> Queue<T> {
+    fn push(&self, value: T) -> Result<(), T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let diff = sequence.wrapping_sub(pos) as isize;
+
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: the successful exchange gives this thread exclusive access
+                        // to the slot until it publishes the new sequence number
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.sequence.store(pos.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(actual) => pos = actual,
+                }
+            } else if diff < 0 {
+                // the slot still holds a value from one lap ago
+                return Err(value);
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Only called by the single receiver, or on drop
+    fn pop(&self) -> Option<T> {
+        let pos = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[pos & self.mask];
+        if slot.sequence.load(Ordering::Acquire) != pos.wrapping_add(1) {
+            return None;
+        }
+
+        // SAFETY: the sequence number shows that the value has been written, and no sender
+        // reuses the slot before the receiver publishes the next lap's sequence number
+        let value = unsafe { (*slot.value.get()).assume_init_read() };
+        slot.sequence
+            .store(pos.wrapping_add(self.slots.len()), Ordering::Release);
+        self.head.store(pos.wrapping_add(1), Ordering::Relaxed);
+        Some(value)
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn values_overflow_the_ring_in_order() {
+        let (sender, mut receiver) = channel(4);
+        for i in 0..10 {
+            sender.send(i);
+        }
+
+        let received: Vec<_> = std::iter::from_fn(|| receiver.recv()).collect();
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+
+        // the ring is used again once the overflow has been received
+        sender.send(10);
+        assert!(!receiver.queue.overflowed.load(Ordering::SeqCst));
+        assert_eq!(receiver.recv(), Some(10));
+        assert_eq!(receiver.recv(), None);
+    }
+
+    #[test]
+    fn values_from_each_sender_arrive_in_order() {
+        let (sender, mut receiver) = channel(16);
+        let senders: Vec<_> = (0..4)
+            .map(|id| {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    for i in 0..10000 {
+                        sender.send((id, i));
+                    }
+                })
+            })
+            .collect();
+
+        let mut next = [0; 4];
+        while next.iter().any(|&n| n < 10000) {
+            if let Some((id, i)) = receiver.recv() {
+                assert_eq!(i, next[id]);
+                next[id] += 1;
+            }
+        }
+        senders.into_iter().for_each(|s| s.join().unwrap());
+        assert_eq!(receiver.recv(), None);
+    }
+
+    #[test]
+    fn try_send_fails_when_the_ring_is_full() {
+        let (sender, mut receiver) = channel(2);
+        assert_eq!(sender.try_send(0), Ok(()));
+        assert_eq!(sender.try_send(1), Ok(()));
+        assert_eq!(sender.try_send(2), Err(2));
+
+        assert_eq!(receiver.recv(), Some(0));
+        assert_eq!(sender.try_send(2), Ok(()));
+        assert_eq!(receiver.recv(), Some(1));
+        assert_eq!(receiver.recv(), Some(2));
+    }
+
+    #[test]
+    fn unreceived_values_are_dropped_with_the_queue() {
+        let value = Arc::new(());
+        let (sender, receiver) = channel(2);
+        for _ in 0..5 {
+            sender.send(value.clone());
+        }
+        drop((sender, receiver));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -1,13 +1,121 @@
 //! Render *B-format* audio streams to streams suitable for playback on audio equipment.
 
-use std::collections::VecDeque;
 use std::fs::File;
 use std::io::{BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
 use std::time::Duration;
 
 use rodio::Source;
 
-use crate::bformat::{Bformat, Bweights};
+use crate::bformat::{AmbisonicOrder, Bformat, BformatFrames, Bweights};
+use crate::convolution::Convolver;
+use crate::error::Error;
+
+/// Decode *B-format* samples to audio channels for playback.
+///
+/// Implement this trait to plug a custom decoder into an `Ambisonic` context with
+/// `PlaybackConfiguration::custom`, or wrap it in a `BstreamRenderer` to render any *B-format*
+/// source.
+pub trait Renderer {
+    /// Number of output channels
+    fn channels(&self) -> u16;
+
+    /// Decode one *B-format* sample into one frame of output.
+    ///
+    /// `output` has exactly `channels()` elements.
+    fn render(&mut self, input: &Bformat, output: &mut [f32]);
+}
+
+impl<R: Renderer + ?Sized> Renderer for Box<R> {
+    fn channels(&self) -> u16 {
+        (**self).channels()
+    }
+
+    fn render(&mut self, input: &Bformat, output: &mut [f32]) {
+        (**self).render(input, output)
+    }
+}
+
+/// Render a *B-format* stream with a custom `Renderer`.
+///
+/// Produces interleaved frames of `renderer.channels()` samples.
+pub struct BstreamRenderer<I, R> {
+    input: BformatFrames<I>,
+    renderer: R,
+    frame: Vec<f32>,
+    position: usize,
+}
+
+impl<I, R: Renderer> BstreamRenderer<I, R> {
+    /// Construct a renderer for the given *B-format* stream
+    pub fn new(input: I, renderer: R) -> Self {
+        let n = renderer.channels() as usize;
+        BstreamRenderer {
+            input: BformatFrames::new(input),
+            renderer,
+            frame: vec![0.0; n],
+            position: n,
+        }
+    }
+
+    /// Access the inner renderer
+    pub fn renderer(&self) -> &R {
+        &self.renderer
+    }
+
+    /// Mutably access the inner renderer
+    pub fn renderer_mut(&mut self) -> &mut R {
+        &mut self.renderer
+    }
+}
+
+impl<I, R> Source for BstreamRenderer<I, R>
+where
+    I: Source<Item = f32>,
+    R: Renderer,
+{
+    #[inline(always)]
+    fn current_frame_len(&self) -> Option<usize> {
+        let channels = Source::channels(self) as usize;
+        self.input.current_frame_count().map(|n| n * channels)
+    }
+
+    #[inline(always)]
+    fn channels(&self) -> u16 {
+        self.frame.len() as u16
+    }
+
+    #[inline(always)]
+    fn sample_rate(&self) -> u32 {
+        self.input.inner().sample_rate()
+    }
+
+    #[inline(always)]
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.inner().total_duration()
+    }
+}
+
+impl<I, R> Iterator for BstreamRenderer<I, R>
+where
+    I: Source<Item = f32>,
+    R: Renderer,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.frame.len() {
+            let sample = self.input.next()?;
+            self.renderer.render(&sample, &mut self.frame);
+            self.position = 0;
+        }
+
+        let output = self.frame[self.position];
+        self.position += 1;
+        Some(output)
+    }
+}
 
 /// Stereo Playback configuration
 ///
@@ -43,9 +151,10 @@ impl Default for StereoConfig {
 
 /// Render a *B-format* stream to a stereo representation.
 ///
-/// Suitable for playback over two speakers arranged in front of the user.
+/// Suitable for playback over two speakers arranged in front of the user. Only the first-order
+/// components of the stream are used.
 pub struct BstreamStereoRenderer<I> {
-    input: I,
+    input: BformatFrames<I>,
     buffered_sample: Option<f32>,
     left_mic: Bweights,
     right_mic: Bweights,
@@ -55,7 +164,7 @@ impl<I> BstreamStereoRenderer<I> {
     /// Construct a new stereo renderer with default settings
     pub fn new(input: I, config: StereoConfig) -> Self {
         BstreamStereoRenderer {
-            input,
+            input: BformatFrames::new(input),
             buffered_sample: None,
             left_mic: config.left_mic,
             right_mic: config.right_mic,
@@ -65,11 +174,12 @@ impl<I> BstreamStereoRenderer<I> {
 
 impl<I> Source for BstreamStereoRenderer<I>
 where
-    I: Source<Item = Bformat>,
+    I: Source<Item = f32>,
 {
     #[inline(always)]
     fn current_frame_len(&self) -> Option<usize> {
-        self.input.current_frame_len()
+        let channels = Source::channels(self) as usize;
+        self.input.current_frame_count().map(|n| n * channels)
     }
 
     #[inline(always)]
@@ -79,18 +189,29 @@ where
 
     #[inline(always)]
     fn sample_rate(&self) -> u32 {
-        self.input.sample_rate()
+        self.input.inner().sample_rate()
     }
 
     #[inline(always)]
     fn total_duration(&self) -> Option<Duration> {
-        self.input.total_duration()
+        self.input.inner().total_duration()
+    }
+}
+
+impl<I> Renderer for BstreamStereoRenderer<I> {
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn render(&mut self, input: &Bformat, output: &mut [f32]) {
+        output[0] = self.left_mic.dot(*input);
+        output[1] = self.right_mic.dot(*input);
     }
 }
 
 impl<I> Iterator for BstreamStereoRenderer<I>
 where
-    I: Source<Item = Bformat>,
+    I: Source<Item = f32>,
 {
     type Item = f32;
 
@@ -100,8 +221,9 @@ where
             None => {
                 let sample = self.input.next()?;
 
-                let left = self.left_mic.dot(sample);
-                let right = self.right_mic.dot(sample);
+                let mut output = [0.0; 2];
+                self.render(&sample, &mut output);
+                let [left, right] = output;
 
                 // emit left channel now, and right channel next time
                 self.buffered_sample = Some(right);
@@ -117,101 +239,232 @@ where
 /// between both ears, depending on the direction of the sound.
 ///
 /// The default setting uses a set of real but arbitrary HRIRs, that may not be suitable for
-/// all listeners.
+/// all listeners. Personalised HRTFs can be loaded from SOFA files with `from_sofa`.
 pub struct HrtfConfig {
-    sample_rate: u32,
-    virtual_speakers: Vec<VirtualSpeaker>,
+    pub(crate) sample_rate: u32,
+    pub(crate) virtual_speakers: Vec<VirtualSpeaker>,
 }
 
 impl HrtfConfig {
-    pub fn from_file(filename: &str) -> Self {
-        // todo: proper error handling
-        let file = File::open(filename).unwrap();
-        let mut bufr = BufReader::new(file);
+    /// Load HRIRs from a `.hrir` file.
+    ///
+    /// See `from_reader` for a description of the format.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    /// Load HRIRs in `.hrir` format from a reader.
+    ///
+    /// The format is line based. The first line contains the sample rate, followed by an empty
+    /// line. Then, each virtual speaker is described by four lines: the speaker's *B-format*
+    /// weights, the left HRIR, the right HRIR, and an empty line. Values on a line are separated
+    /// by commas. The weights may be given for first, second or third order (4, 9 or 16 values).
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
         let mut data = String::new();
-        bufr.read_to_string(&mut data).unwrap();
+        reader.read_to_string(&mut data)?;
+        data.parse()
+    }
+
+    /// The highest ambisonic order decoded by the virtual speakers.
+    pub fn order(&self) -> AmbisonicOrder {
+        self.virtual_speakers
+            .iter()
+            .map(|speaker| speaker.bweights.order())
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for HrtfConfig {
+    type Err = Error;
 
-        let mut lines = data.split('\n');
-        let fs: f32 = lines.next().unwrap().parse().unwrap();
-        assert_eq!(lines.next(), Some(""));
+    /// Parse HRIRs in `.hrir` format.
+    ///
+    /// See `from_reader` for a description of the format.
+    fn from_str(data: &str) -> Result<Self, Error> {
+        let mut lines = HrirLines::new(data);
+
+        let (n, line) = lines.expect("sample rate")?;
+        let sample_rate: f32 = parse_value(n, line)?;
+        if !(sample_rate.is_finite() && sample_rate > 0.0) {
+            return Err(hrir_error(
+                n,
+                format!("invalid sample rate {}", sample_rate),
+            ));
+        }
+        lines.expect_blank()?;
 
         let mut virtual_speakers = Vec::new();
 
-        loop {
-            let bweights: Bweights = match lines.next() {
-                None | Some("") => break,
-                Some(l) => l.split(", ").map(|s| s.parse().unwrap()).collect(),
-            };
+        while let Some((n, line)) = lines.next_non_blank() {
+            let weights = parse_list(n, line)?;
+            if AmbisonicOrder::from_channels(weights.len()).is_none() {
+                return Err(hrir_error(
+                    n,
+                    format!("expected 4, 9 or 16 weights, found {}", weights.len()),
+                ));
+            }
 
-            let left_hrir: Vec<f32> = match lines.next() {
-                None | Some("") => panic!("expected hrir"),
-                Some(l) => l.split(", ").map(|s| s.parse().unwrap()).collect(),
-            };
+            let (n, line) = lines.expect("left HRIR")?;
+            let left_hrir = parse_list(n, line)?;
 
-            let right_hrir: Vec<f32> = match lines.next() {
-                None | Some("") => panic!("expected hrir"),
-                Some(l) => l.split(", ").map(|s| s.parse().unwrap()).collect(),
-            };
+            let (n, line) = lines.expect("right HRIR")?;
+            let right_hrir = parse_list(n, line)?;
 
-            assert_eq!(lines.next(), Some(""));
+            lines.expect_blank()?;
 
             virtual_speakers.push(VirtualSpeaker {
-                bweights,
+                bweights: weights.into_iter().collect(),
                 left_hrir,
                 right_hrir,
             });
         }
 
-        assert!(lines.next().is_none());
+        if virtual_speakers.is_empty() {
+            return Err(hrir_error(lines.line_number(), "no virtual speakers"));
+        }
 
-        HrtfConfig {
-            sample_rate: fs as u32,
+        Ok(HrtfConfig {
+            sample_rate: sample_rate as u32,
             virtual_speakers,
+        })
+    }
+}
+
+/// Iterate over lines of `.hrir` data, keeping track of line numbers
+struct HrirLines<'a> {
+    lines: std::iter::Peekable<std::iter::Enumerate<std::str::Lines<'a>>>,
+    line_number: usize,
+}
+
+impl<'a> HrirLines<'a> {
+    fn new(data: &'a str) -> Self {
+        HrirLines {
+            lines: data.lines().enumerate().peekable(),
+            line_number: 0,
+        }
+    }
+
+    /// Number of the last line consumed (starting with 1)
+    fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        let (i, line) = self.lines.next()?;
+        self.line_number = i + 1;
+        Some((i + 1, line.trim_end()))
+    }
+
+    /// Consume a non-blank line describing `what`
+    fn expect(&mut self, what: &str) -> Result<(usize, &'a str), Error> {
+        match self.next() {
+            Some((n, "")) => Err(hrir_error(n, format!("expected {}", what))),
+            None => Err(hrir_error(
+                self.line_number + 1,
+                format!("expected {}, found end of data", what),
+            )),
+            Some(line) => Ok(line),
+        }
+    }
+
+    /// Consume a blank line, or the end of the data
+    fn expect_blank(&mut self) -> Result<(), Error> {
+        match self.next() {
+            Some((_, "")) | None => Ok(()),
+            Some((n, _)) => Err(hrir_error(n, "expected empty line")),
+        }
+    }
+
+    /// Skip blank lines and return the next non-blank line, if any
+    fn next_non_blank(&mut self) -> Option<(usize, &'a str)> {
+        loop {
+            match self.next()? {
+                (_, "") => continue,
+                line => return Some(line),
+            }
         }
     }
 }
 
+fn parse_value(line_number: usize, s: &str) -> Result<f32, Error> {
+    let s = s.trim();
+    s.parse()
+        .map_err(|_| hrir_error(line_number, format!("invalid number '{}'", s)))
+}
+
+fn parse_list(line_number: usize, line: &str) -> Result<Vec<f32>, Error> {
+    line.split(',')
+        .map(|s| parse_value(line_number, s))
+        .collect()
+}
+
+fn hrir_error(line: usize, message: impl Into<String>) -> Error {
+    Error::ParseHrir {
+        line,
+        message: message.into(),
+    }
+}
+
 /// Render a *B-format* stream for headphones using head related transfer functions.
 pub struct BstreamHrtfRenderer<I> {
-    input: I,
+    input: BformatFrames<I>,
     buffered_output: Option<f32>,
-    convolution_buffers: Vec<VecDeque<f32>>,
-    virtual_speakers: Vec<VirtualSpeaker>,
+    n_channels: usize,
+    convolver: Convolver,
 }
 
 impl<I> BstreamHrtfRenderer<I>
 where
-    I: Source<Item = Bformat>,
+    I: Source<Item = f32>,
 {
     /// Construct a new HRTF renderer with default settings
     pub fn new(input: I, config: HrtfConfig) -> Self {
         assert_eq!(config.sample_rate, input.sample_rate());
 
-        let convolution_buffers = config
-            .virtual_speakers
-            .iter()
-            .map(|speaker| {
-                let n = speaker.left_hrir.len().max(speaker.right_hrir.len());
-                VecDeque::from(vec![0.0; n])
+        // Decoding and convolution are both linear, so the virtual speakers are combined into
+        // one pair of filters per B-format channel.
+        let n_channels = config.order().channels();
+        let filters: Vec<Vec<Vec<f32>>> = (0..n_channels)
+            .map(|c| {
+                let mut left = vec![];
+                let mut right = vec![];
+                for speaker in &config.virtual_speakers {
+                    let w = speaker.bweights.components()[c];
+                    accumulate_scaled(&mut left, &speaker.left_hrir, w);
+                    accumulate_scaled(&mut right, &speaker.right_hrir, w);
+                }
+                vec![left, right]
             })
             .collect();
 
         BstreamHrtfRenderer {
-            input,
+            input: BformatFrames::new(input),
             buffered_output: None,
-            convolution_buffers,
-            virtual_speakers: config.virtual_speakers,
+            n_channels,
+            convolver: Convolver::new(&filters),
         }
     }
 }
 
+fn accumulate_scaled(acc: &mut Vec<f32>, x: &[f32], scale: f32) {
+    if acc.len() < x.len() {
+        acc.resize(x.len(), 0.0);
+    }
+    for (a, x) in acc.iter_mut().zip(x) {
+        *a += x * scale;
+    }
+}
+
 impl<I> Source for BstreamHrtfRenderer<I>
 where
-    I: Source<Item = Bformat>,
+    I: Source<Item = f32>,
 {
     #[inline(always)]
     fn current_frame_len(&self) -> Option<usize> {
-        self.input.current_frame_len()
+        let channels = Source::channels(self) as usize;
+        self.input.current_frame_count().map(|n| n * channels)
     }
 
     #[inline(always)]
@@ -221,18 +474,29 @@ where
 
     #[inline(always)]
     fn sample_rate(&self) -> u32 {
-        self.input.sample_rate()
+        self.input.inner().sample_rate()
     }
 
     #[inline(always)]
     fn total_duration(&self) -> Option<Duration> {
-        self.input.total_duration()
+        self.input.inner().total_duration()
+    }
+}
+
+impl<I> Renderer for BstreamHrtfRenderer<I> {
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn render(&mut self, input: &Bformat, output: &mut [f32]) {
+        self.convolver
+            .process(&input.components()[..self.n_channels], output);
     }
 }
 
 impl<I> Iterator for BstreamHrtfRenderer<I>
 where
-    I: Source<Item = Bformat>,
+    I: Source<Item = f32>,
 {
     type Item = f32;
 
@@ -242,29 +506,9 @@ where
             None => {
                 let sample = self.input.next()?;
 
-                let (mut left, mut right) = (0.0, 0.0);
-
-                for (speaker, buffer) in self
-                    .virtual_speakers
-                    .iter()
-                    .zip(self.convolution_buffers.iter_mut())
-                {
-                    let signal = speaker.bweights.dot(sample);
-                    buffer.pop_back();
-                    buffer.push_front(signal);
-
-                    left += buffer
-                        .iter()
-                        .zip(&speaker.left_hrir)
-                        .map(|(s, h)| s * h)
-                        .sum::<f32>();
-
-                    right += buffer
-                        .iter()
-                        .zip(&speaker.right_hrir)
-                        .map(|(s, h)| s * h)
-                        .sum::<f32>();
-                }
+                let mut output = [0.0; 2];
+                self.render(&sample, &mut output);
+                let [left, right] = output;
 
                 // emit left channel now, and right channel next time
                 self.buffered_output = Some(right);
@@ -274,10 +518,10 @@ where
     }
 }
 
-struct VirtualSpeaker {
-    bweights: Bweights,
-    left_hrir: Vec<f32>,
-    right_hrir: Vec<f32>,
+pub(crate) struct VirtualSpeaker {
+    pub(crate) bweights: Bweights,
+    pub(crate) left_hrir: Vec<f32>,
+    pub(crate) right_hrir: Vec<f32>,
 }
 
 #[allow(clippy::excessive_precision)]
@@ -2382,3 +2626,53 @@ impl Default for HrtfConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL_HRIR: &str = "48000\n\n1, 0, 0, 0\n0.5, 0.25\n0.25, 0.5\n\n";
+
+    fn parse_error_line(data: &str) -> usize {
+        match data.parse::<HrtfConfig>() {
+            Err(Error::ParseHrir { line, .. }) => line,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("parsing should fail"),
+        }
+    }
+
+    #[test]
+    fn parse_bundled_hrir_file() {
+        let cfg: HrtfConfig = include_str!("../tools/test.hrir").parse().unwrap();
+        assert_eq!(cfg.sample_rate, 48000);
+        assert_eq!(cfg.virtual_speakers.len(), 4);
+    }
+
+    #[test]
+    fn parse_windows_line_endings_and_trailing_whitespace() {
+        let data = MINIMAL_HRIR.replace('\n', " \r\n");
+        let cfg: HrtfConfig = data.parse().unwrap();
+        assert_eq!(cfg.virtual_speakers[0].left_hrir, vec![0.5, 0.25]);
+    }
+
+    #[test]
+    fn parse_from_reader() {
+        let cfg = HrtfConfig::from_reader(MINIMAL_HRIR.as_bytes()).unwrap();
+        assert_eq!(cfg.virtual_speakers.len(), 1);
+    }
+
+    #[test]
+    fn invalid_numbers_are_reported_with_line_number() {
+        assert_eq!(parse_error_line("48000\n\n1, 0, 0, 0\n0.5, x\n0.5\n"), 4);
+    }
+
+    #[test]
+    fn wrong_number_of_weights_is_rejected() {
+        assert_eq!(parse_error_line("48000\n\n1, 0, 0\n0.5\n0.5\n"), 3);
+    }
+
+    #[test]
+    fn missing_hrir_is_rejected() {
+        assert_eq!(parse_error_line("48000\n\n1, 0, 0, 0\n0.5\n\n"), 5);
+    }
+}
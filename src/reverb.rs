@@ -0,0 +1,262 @@
+//! Late reverberation in the *B-format* domain
+//!
+//! The reverb is a feedback delay network with one delay line per direction of a spherical
+//! point set. The *B-format* input is sampled in these directions, and the output of each delay
+//! line is encoded back into its direction, which produces a diffuse, decorrelated tail that
+//! surrounds the listener.
+
+use std::time::Duration;
+
+use crate::bformat::{AmbisonicOrder, Bformat, Bweights};
+use crate::constants::SPEED_OF_SOUND;
+use crate::decoder::{self, DecoderMethod};
+use crate::room::Room;
+use crate::sphere;
+
+/// Number of delay lines in the feedback network (must be a power of two)
+const N_LINES: usize = 16;
+
+/// Delay line lengths in milliseconds
+const DELAY_TIMES: [f32; N_LINES] = [
+    29.7, 33.1, 37.1, 41.1, 43.7, 47.3, 53.9, 59.3, 61.7, 67.1, 71.3, 73.9, 79.7, 83.1, 89.3, 97.1,
+];
+
+/// Reverb settings of a sound scene
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReverbConfig {
+    decay_time: f32,
+    damping: f32,
+    pre_delay: Duration,
+    gain: f32,
+}
+
+impl Default for ReverbConfig {
+    fn default() -> Self {
+        ReverbConfig {
+            decay_time: 1.5,
+            damping: 0.3,
+            pre_delay: Duration::from_millis(20),
+            gain: 0.25,
+        }
+    }
+}
+
+impl ReverbConfig {
+    /// Create new `ReverbConfig` with default settings.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Estimate reverb settings for a room.
+    ///
+    /// The decay time follows Sabine's formula, and the pre-delay is the time sound takes to
+    /// travel the room's mean free path.
+    pub fn from_room(room: &Room) -> Self {
+        let [w, d, h] = room.dimensions;
+        let volume = w * d * h;
+        let surface = 2.0 * (w * d + w * h + d * h);
+        let absorption = (1.0 - room.reflectivity * room.reflectivity).max(1e-3);
+
+        ReverbConfig::new()
+            .with_decay_time(0.161 * volume / (surface * absorption))
+            .with_pre_delay(Duration::from_secs_f32(
+                4.0 * volume / surface / SPEED_OF_SOUND,
+            ))
+    }
+
+    /// Set the time in seconds it takes the reverb to decay by 60 dB (defaults to 1.5).
+    pub fn with_decay_time(mut self, seconds: f32) -> Self {
+        self.decay_time = seconds;
+        self
+    }
+
+    /// Set how much faster high frequencies decay, between 0 and 1 (defaults to 0.3).
+    pub fn with_damping(mut self, damping: f32) -> Self {
+        self.damping = damping;
+        self
+    }
+
+    /// Set the delay between the direct sound and the onset of the reverb (defaults to 20 ms).
+    pub fn with_pre_delay(mut self, pre_delay: Duration) -> Self {
+        self.pre_delay = pre_delay;
+        self
+    }
+
+    /// Set the level of the reverb relative to the direct sound (defaults to 0.25).
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+}
+
+struct DelayLine {
+    buffer: Vec<f32>,
+    position: usize,
+    feedback: f32,
+    lowpass: f32,
+}
+
+/// Feedback delay network reverb
+pub(crate) struct Reverb {
+    pre_delay: Vec<Bformat>,
+    pre_delay_position: usize,
+    lines: Vec<DelayLine>,
+    damping: f32,
+    input_weights: Vec<Bweights>,
+    output_weights: Vec<Bweights>,
+}
+
+impl Reverb {
+    pub(crate) fn new(config: &ReverbConfig, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+        let decay_time = config.decay_time.max(1e-3);
+
+        let lines = DELAY_TIMES
+            .iter()
+            .map(|ms| {
+                let len = ((ms / 1000.0 * sample_rate).round() as usize).max(1);
+                DelayLine {
+                    buffer: vec![0.0; len],
+                    position: 0,
+                    // attenuate by 60 dB per decay time
+                    feedback: 10f32.powf(-3.0 * len as f32 / (sample_rate * decay_time)),
+                    lowpass: 0.0,
+                }
+            })
+            .collect();
+
+        let directions = sphere::fibonacci(N_LINES);
+        let input_weights =
+            decoder::design(&directions, AmbisonicOrder::First, DecoderMethod::Sampling);
+        let output_gain = config.gain / (N_LINES as f32).sqrt();
+        let output_weights = directions
+            .iter()
+            .map(|&d| {
+                let mut weights = Bweights::from_direction(d);
+                weights.components_mut()[AmbisonicOrder::First.channels()..].fill(0.0);
+                weights.amplify(output_gain);
+                weights
+            })
+            .collect();
+
+        let pre_delay = (config.pre_delay.as_secs_f32() * sample_rate).round() as usize;
+
+        Reverb {
+            pre_delay: vec![Bformat::zero(); pre_delay.max(1)],
+            pre_delay_position: 0,
+            lines,
+            damping: config.damping.clamp(0.0, 0.99),
+            input_weights,
+            output_weights,
+        }
+    }
+
+    /// Feed the next input sample and return the reverb's output
+    pub(crate) fn process(&mut self, input: Bformat) -> Bformat {
+        let input = std::mem::replace(&mut self.pre_delay[self.pre_delay_position], input);
+        self.pre_delay_position = (self.pre_delay_position + 1) % self.pre_delay.len();
+
+        let mut output = Bformat::zero();
+        let mut feedback = [0.0; N_LINES];
+        for ((line, weights), fb) in self
+            .lines
+            .iter_mut()
+            .zip(&self.output_weights)
+            .zip(&mut feedback)
+        {
+            let x = line.buffer[line.position];
+            output += weights.scale(x);
+
+            line.lowpass = x * (1.0 - self.damping) + line.lowpass * self.damping;
+            *fb = line.lowpass * line.feedback;
+        }
+
+        hadamard(&mut feedback);
+
+        for ((line, weights), fb) in self
+            .lines
+            .iter_mut()
+            .zip(&self.input_weights)
+            .zip(&feedback)
+        {
+            line.buffer[line.position] = fb + weights.dot(input);
+            line.position = (line.position + 1) % line.buffer.len();
+        }
+
+        output
+    }
+}
+
+/// Orthonormal Hadamard transform, which mixes the delay lines without changing energy
+fn hadamard(x: &mut [f32; N_LINES]) {
+    let mut h = 1;
+    while h < N_LINES {
+        for i in (0..N_LINES).step_by(2 * h) {
+            for j in i..i + h {
+                let (a, b) = (x[j], x[j + h]);
+                x[j] = a + b;
+                x[j + h] = a - b;
+            }
+        }
+        h *= 2;
+    }
+
+    let scale = 1.0 / (N_LINES as f32).sqrt();
+    for x in x.iter_mut() {
+        *x *= scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn energy(response: &[Bformat]) -> f32 {
+        response
+            .iter()
+            .flat_map(|x| x.components())
+            .map(|x| x * x)
+            .sum()
+    }
+
+    #[test]
+    fn reverb_decays_by_60_db_per_decay_time() {
+        let config = ReverbConfig::new()
+            .with_decay_time(0.5)
+            .with_damping(0.0)
+            .with_pre_delay(Duration::from_millis(0));
+        let mut reverb = Reverb::new(&config, 10000);
+
+        let impulse = Bweights::from_direction([1.0, 0.0, 0.0]).scale(1.0);
+        let response: Vec<Bformat> = (0..10000)
+            .map(|i| reverb.process(if i == 0 { impulse } else { Bformat::zero() }))
+            .collect();
+
+        let early = energy(&response[1000..2000]);
+        let late = energy(&response[6000..7000]);
+        let decay = 10.0 * (early / late).log10();
+        assert!((decay - 60.0).abs() < 6.0, "decay = {} dB", decay);
+    }
+
+    #[test]
+    fn reverb_starts_after_pre_delay() {
+        let config = ReverbConfig::new().with_pre_delay(Duration::from_millis(50));
+        let mut reverb = Reverb::new(&config, 1000);
+
+        let impulse = Bweights::omni_source().scale(1.0);
+        let response: Vec<Bformat> = (0..200)
+            .map(|i| reverb.process(if i == 0 { impulse } else { Bformat::zero() }))
+            .collect();
+
+        let first = response.iter().position(|x| energy(&[*x]) > 0.0).unwrap();
+        assert_eq!(first, 50 + (DELAY_TIMES[0].round() as usize));
+    }
+
+    #[test]
+    fn hadamard_transform_preserves_energy() {
+        let mut x = [0.0; N_LINES];
+        x[3] = 1.0;
+        hadamard(&mut x);
+        assert!(x.iter().all(|&v| (v.abs() - 0.25).abs() < 1e-6));
+    }
+}
@@ -0,0 +1,196 @@
+//! Early reflections of a rectangular room
+
+use crate::bformat::{norm, Bformat, Bweights};
+use crate::distance::DistanceModel;
+
+/// Rectangular ("shoebox") room
+///
+/// The room is aligned with the coordinate axes of the sound scene: its walls are perpendicular
+/// to `x` (left and right), `y` (front and back), and `z` (floor and ceiling). Each source
+/// produces one first-order reflection per wall, which is rendered with the direction, delay,
+/// and attenuation of the corresponding image source.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Room {
+    pub(crate) dimensions: [f32; 3],
+    pub(crate) listener: [f32; 3],
+    pub(crate) reflectivity: f32,
+}
+
+impl Room {
+    /// A room of given width (`x`), depth (`y`), and height (`z`), with the listener at its
+    /// center.
+    pub fn shoebox(dimensions: [f32; 3]) -> Self {
+        Room {
+            dimensions,
+            listener: [
+                dimensions[0] / 2.0,
+                dimensions[1] / 2.0,
+                dimensions[2] / 2.0,
+            ],
+            reflectivity: 0.7,
+        }
+    }
+
+    /// Set the listener's position inside the room.
+    ///
+    /// The position is measured from the corner at the left, back, and bottom of the room.
+    pub fn with_listener_position(mut self, position: [f32; 3]) -> Self {
+        self.listener = position;
+        self
+    }
+
+    /// Set the fraction of sound pressure reflected by the walls (defaults to 0.7).
+    pub fn with_reflectivity(mut self, reflectivity: f32) -> Self {
+        self.reflectivity = reflectivity;
+        self
+    }
+
+    /// Positions of the first-order image sources, relative to the listener
+    ///
+    /// `position` is the position of the source relative to the listener.
+    fn image_sources(&self, position: [f32; 3]) -> [[f32; 3]; 6] {
+        let mut images = [position; 6];
+        for axis in 0..3 {
+            let l = self.listener[axis];
+            images[2 * axis][axis] = -2.0 * l - position[axis];
+            images[2 * axis + 1][axis] = 2.0 * (self.dimensions[axis] - l) - position[axis];
+        }
+        images
+    }
+
+    /// Longest distance between two points in the room
+    fn diagonal(&self) -> f32 {
+        let [w, d, h] = self.dimensions;
+        (w * w + d * d + h * h).sqrt()
+    }
+}
+
+/// Reflection of a single wall
+#[derive(Default, Copy, Clone)]
+struct Tap {
+    delay: f32,
+    target_delay: f32,
+    weights: Bweights,
+    target_weights: Bweights,
+}
+
+/// First-order reflections of a single source
+pub(crate) struct EarlyReflections {
+    room: Room,
+    sample_rate: f32,
+    speed_of_sound: f32,
+    buffer: Vec<f32>,
+    position: usize,
+    taps: [Tap; 6],
+    initialized: bool,
+}
+
+impl EarlyReflections {
+    pub(crate) fn new(room: Room, sample_rate: u32, speed_of_sound: f32) -> Self {
+        // a reflection's path is longer than the direct path by at most twice the room's extent
+        let max_delay = 2.0 * room.diagonal() / speed_of_sound * sample_rate as f32;
+        EarlyReflections {
+            room,
+            sample_rate: sample_rate as f32,
+            speed_of_sound,
+            buffer: vec![0.0; max_delay.ceil() as usize + 2],
+            position: 0,
+            taps: [Tap::default(); 6],
+            initialized: false,
+        }
+    }
+
+    /// Room whose reflections these are
+    pub(crate) fn room(&self) -> &Room {
+        &self.room
+    }
+
+    pub(crate) fn speed_of_sound(&self) -> f32 {
+        self.speed_of_sound
+    }
+
+    /// Update the reflections for a source at `position` relative to the listener
+    ///
+    /// Reflections transition smoothly, except for the first update, which takes effect
+    /// immediately.
+    pub(crate) fn set_source(&mut self, position: [f32; 3], model: &DistanceModel) {
+        let direct = norm(position);
+        let max_delay = (self.buffer.len() - 2) as f32;
+
+        for (tap, image) in self.taps.iter_mut().zip(self.room.image_sources(position)) {
+            let extra_path = norm(image) - direct;
+            tap.target_delay =
+                (extra_path / self.speed_of_sound * self.sample_rate).clamp(0.0, max_delay);
+            tap.target_weights = Bweights::from_position(image, model);
+            tap.target_weights.amplify(self.room.reflectivity);
+
+            if !self.initialized {
+                tap.delay = tap.target_delay;
+                tap.weights = tap.target_weights;
+            }
+        }
+
+        self.initialized = true;
+    }
+
+    /// Feed the next sample of the direct sound and return the reflections
+    pub(crate) fn process(&mut self, x: f32) -> Bformat {
+        let len = self.buffer.len();
+        self.buffer[self.position] = x;
+
+        let mut output = Bformat::zero();
+        for tap in &mut self.taps {
+            tap.delay += (tap.target_delay - tap.delay) * DELAY_SMOOTHING;
+            tap.weights.approach(&tap.target_weights, 0.001);
+
+            let i = tap.delay as usize;
+            let f = tap.delay - i as f32;
+            let a = self.buffer[(self.position + len - i) % len];
+            let b = self.buffer[(self.position + 2 * len - i - 1) % len];
+            output += tap.weights.scale(a * (1.0 - f) + b * f);
+        }
+
+        self.position = (self.position + 1) % len;
+        output
+    }
+}
+
+/// Fraction of the remaining change of reflection delays applied per sample
+const DELAY_SMOOTHING: f32 = 0.0005;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_sources_are_mirrored_at_the_walls() {
+        let room = Room::shoebox([4.0, 6.0, 3.0]).with_listener_position([1.0, 2.0, 1.5]);
+        let images = room.image_sources([1.0, 0.0, 0.0]);
+
+        // source at (2, 2, 1.5) in room coordinates
+        assert_eq!(images[0], [-3.0, 0.0, 0.0]);
+        assert_eq!(images[1], [5.0, 0.0, 0.0]);
+        assert_eq!(images[2], [1.0, -4.0, 0.0]);
+        assert_eq!(images[3], [1.0, 8.0, 0.0]);
+        assert_eq!(images[4], [1.0, 0.0, -3.0]);
+        assert_eq!(images[5], [1.0, 0.0, 3.0]);
+    }
+
+    #[test]
+    fn reflections_arrive_after_the_direct_sound() {
+        let room = Room::shoebox([10.0, 10.0, 10.0]).with_reflectivity(1.0);
+        let mut reflections = EarlyReflections::new(room, 1000, 100.0);
+        reflections.set_source([0.0, 1.0, 0.0], &DistanceModel::none());
+
+        let response: Vec<f32> = (0..200)
+            .map(|i| reflections.process(if i == 0 { 1.0 } else { 0.0 }).w())
+            .collect();
+
+        // the wall behind the source is 4 m away, so its reflection travels 8 m further
+        let first = response.iter().position(|&x| x != 0.0).unwrap();
+        assert_eq!(first, 80);
+        // the side walls reflect along a path that is about 9 m longer
+        assert!(response[82..90].iter().all(|&x| x == 0.0));
+        assert!(response[90] + response[91] > 0.0);
+    }
+}
@@ -0,0 +1,372 @@
+//! Listener orientation and rotation of *B-format* sound fields
+
+use crate::bformat::{AmbisonicOrder, Bformat, Bweights, MAX_CHANNELS};
+use crate::linalg;
+use crate::sphere;
+use std::f32::consts::FRAC_PI_2;
+
+/// Time over which changes of the listener orientation are smoothed, in seconds
+const ROTATION_SMOOTHING: f32 = 0.02;
+
+/// Orientation of the listener
+///
+/// In the default orientation the listener faces `+y`, with `+x` to the right and `+z` up.
+/// Source positions are given in this coordinate system relative to the listener's position,
+/// regardless of where the listener is facing.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Orientation {
+    // rotation matrix from listener coordinates to scene coordinates
+    matrix: [[f32; 3]; 3],
+}
+
+impl Orientation {
+    /// The default orientation
+    pub fn identity() -> Self {
+        Orientation {
+            matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Construct orientation from Tait-Bryan angles in radians.
+    ///
+    /// The rotations are applied in the order yaw, pitch, roll. Positive `yaw` turns the
+    /// listener to the left (around `z`), positive `pitch` makes the listener look up (around
+    /// `x`), and positive `roll` tilts the listener's head to the right (around `y`).
+    pub fn from_yaw_pitch_roll(yaw: f32, pitch: f32, roll: f32) -> Self {
+        let (sy, cy) = yaw.sin_cos();
+        let (sp, cp) = pitch.sin_cos();
+        let (sr, cr) = roll.sin_cos();
+
+        let rz = [[cy, -sy, 0.0], [sy, cy, 0.0], [0.0, 0.0, 1.0]];
+        let rx = [[1.0, 0.0, 0.0], [0.0, cp, -sp], [0.0, sp, cp]];
+        let ry = [[cr, 0.0, sr], [0.0, 1.0, 0.0], [-sr, 0.0, cr]];
+
+        Orientation {
+            matrix: mul3(&mul3(&rz, &rx), &ry),
+        }
+    }
+
+    /// Construct orientation from a rotation quaternion `[w, x, y, z]`.
+    ///
+    /// The quaternion rotates listener coordinates into scene coordinates. It does not need to
+    /// be normalized.
+    pub fn from_quaternion(q: [f32; 4]) -> Self {
+        let l = q.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let [w, x, y, z] = [q[0] / l, q[1] / l, q[2] / l, q[3] / l];
+
+        Orientation {
+            matrix: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                ],
+            ],
+        }
+    }
+
+    /// Direction the listener is facing, in scene coordinates
+    pub fn forward(&self) -> [f32; 3] {
+        self.to_scene([0.0, 1.0, 0.0])
+    }
+
+    /// Direction of the top of the listener's head, in scene coordinates
+    pub fn up(&self) -> [f32; 3] {
+        self.to_scene([0.0, 0.0, 1.0])
+    }
+
+    /// Transform a vector from listener coordinates to scene coordinates
+    pub fn to_scene(&self, v: [f32; 3]) -> [f32; 3] {
+        let m = &self.matrix;
+        [
+            m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+            m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+            m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+        ]
+    }
+
+    /// Transform a vector from scene coordinates to listener coordinates
+    pub fn to_listener(&self, v: [f32; 3]) -> [f32; 3] {
+        let m = &self.matrix;
+        [
+            m[0][0] * v[0] + m[1][0] * v[1] + m[2][0] * v[2],
+            m[0][1] * v[0] + m[1][1] * v[1] + m[2][1] * v[2],
+            m[0][2] * v[0] + m[1][2] * v[1] + m[2][2] * v[2],
+        ]
+    }
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Orientation::identity()
+    }
+}
+
+fn mul3(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut c = [[0.0; 3]; 3];
+    for (i, row) in c.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    c
+}
+
+/// Linear transformation of *B-format* samples that rotates the sound field
+#[derive(Debug, Copy, Clone)]
+pub struct BformatRotation {
+    matrix: [[f32; MAX_CHANNELS]; MAX_CHANNELS],
+}
+
+impl BformatRotation {
+    /// Rotation that leaves the sound field unchanged
+    pub fn identity() -> Self {
+        let mut matrix = [[0.0; MAX_CHANNELS]; MAX_CHANNELS];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        BformatRotation { matrix }
+    }
+
+    /// Rotation that transforms a sound field in scene coordinates into the coordinates of a
+    /// listener with the given orientation.
+    pub fn from_orientation(orientation: &Orientation) -> Self {
+        // A rotated plane wave is again a plane wave, so the rotation matrix is determined by
+        // mapping the encodings of a set of directions to the encodings of the rotated
+        // directions.
+        let directions = sphere::design_for_order(AmbisonicOrder::Third);
+        let encode = |d: [f32; 3]| -> Vec<f64> {
+            Bweights::from_direction(d)
+                .components()
+                .iter()
+                .map(|&x| x as f64)
+                .collect()
+        };
+
+        let original: Vec<_> = directions.iter().map(|&d| encode(d)).collect();
+        let rotated: Vec<_> = directions
+            .iter()
+            .map(|&d| encode(orientation.to_listener(d)))
+            .collect();
+
+        let decode = linalg::pseudo_inverse(&linalg::transpose(&original))
+            .expect("rotation sampling directions are degenerate");
+        let m = linalg::matmul(&linalg::transpose(&rotated), &decode);
+
+        let mut matrix = [[0.0; MAX_CHANNELS]; MAX_CHANNELS];
+        for (row, mrow) in matrix.iter_mut().zip(m) {
+            for (x, y) in row.iter_mut().zip(mrow) {
+                *x = y as f32;
+            }
+        }
+        BformatRotation { matrix }
+    }
+
+    /// Rotate a *B-format* sample of given order
+    pub fn apply(&self, b: Bformat, order: AmbisonicOrder) -> Bformat {
+        let n = order.channels();
+        let input = b.components();
+        let mut output = [0.0; MAX_CHANNELS];
+        for (y, row) in output.iter_mut().zip(&self.matrix).take(n) {
+            *y = row[..n].iter().zip(&input[..n]).map(|(m, x)| m * x).sum();
+        }
+        Bformat::from_components(output)
+    }
+
+    /// Correlation of the outputs of two rotations for a diffuse sound field of given order,
+    /// clamped to `[0, 1]`
+    fn correlation(&self, other: &BformatRotation, order: AmbisonicOrder) -> f32 {
+        let n = order.channels();
+        let inner = |a: &Self, b: &Self| -> f32 {
+            a.matrix[..n]
+                .iter()
+                .zip(&b.matrix[..n])
+                .map(|(x, y)| x[..n].iter().zip(&y[..n]).map(|(x, y)| x * y).sum::<f32>())
+                .sum()
+        };
+        let norms = (inner(self, self) * inner(other, other)).sqrt();
+        if norms > 0.0 {
+            (inner(self, other) / norms).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Rotation that transitions smoothly to new targets to avoid zipper noise
+///
+/// Blending rotation matrices element-wise does not yield a rotation and loses energy on large
+/// turns. Instead, the outputs of the old and the new rotation are crossfaded with equal-power
+/// gains, normalized by the correlation of the two rotations so that small turns do not bump
+/// the level either. A target set during a transition is started when the transition completes.
+pub struct SmoothRotation {
+    current: BformatRotation,
+    current_is_identity: bool,
+    target: BformatRotation,
+    target_is_identity: bool,
+    pending: Option<(BformatRotation, bool)>,
+    correlation: Option<f32>,
+    remaining: u32,
+    ramp_length: u32,
+}
+
+impl SmoothRotation {
+    pub fn new(sample_rate: u32) -> Self {
+        SmoothRotation {
+            current: BformatRotation::identity(),
+            current_is_identity: true,
+            target: BformatRotation::identity(),
+            target_is_identity: true,
+            pending: None,
+            correlation: None,
+            remaining: 0,
+            ramp_length: ((sample_rate as f32 * ROTATION_SMOOTHING) as u32).max(1),
+        }
+    }
+
+    /// Start transition towards a new rotation
+    pub fn set_target(&mut self, target: BformatRotation, is_identity: bool) {
+        if self.remaining > 0 {
+            self.pending = Some((target, is_identity));
+        } else {
+            self.start(target, is_identity);
+        }
+    }
+
+    fn start(&mut self, target: BformatRotation, is_identity: bool) {
+        self.target = target;
+        self.target_is_identity = is_identity;
+        self.correlation = None;
+        self.remaining = self.ramp_length;
+    }
+
+    /// Rotate the next sample
+    pub fn process(&mut self, b: Bformat, order: AmbisonicOrder) -> Bformat {
+        if self.remaining == 0 {
+            if let Some((target, is_identity)) = self.pending.take() {
+                self.start(target, is_identity);
+            }
+        }
+
+        if self.remaining == 0 {
+            return if self.current_is_identity {
+                b
+            } else {
+                self.current.apply(b, order)
+            };
+        }
+
+        let (current, target) = (&self.current, &self.target);
+        let correlation = *self
+            .correlation
+            .get_or_insert_with(|| current.correlation(target, order));
+
+        let progress = 1.0 - (self.remaining - 1) as f32 / self.ramp_length as f32;
+        let (new_gain, old_gain) = (progress * FRAC_PI_2).sin_cos();
+        // Power of the sum of two partially correlated signals of equal power
+        let power = 1.0 + 2.0 * old_gain * new_gain * correlation;
+        let normalize = 1.0 / power.sqrt();
+
+        let old = if self.current_is_identity {
+            b
+        } else {
+            self.current.apply(b, order)
+        };
+        let new = if self.target_is_identity {
+            b
+        } else {
+            self.target.apply(b, order)
+        };
+
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.current = self.target;
+            self.current_is_identity = self.target_is_identity;
+        }
+
+        (old * old_gain + new * new_gain) * normalize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_vec_close(a: &[f32], b: &[f32]) {
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn yaw_turns_listener_to_the_left() {
+        let o = Orientation::from_yaw_pitch_roll(FRAC_PI_2, 0.0, 0.0);
+        assert_vec_close(&o.forward(), &[-1.0, 0.0, 0.0]);
+        assert_vec_close(&o.to_listener([0.0, 1.0, 0.0]), &[1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn quaternion_and_angles_agree() {
+        let angle = 0.3f32;
+        let a = Orientation::from_yaw_pitch_roll(0.0, angle, 0.0);
+        let q = Orientation::from_quaternion([(angle / 2.0).cos(), (angle / 2.0).sin(), 0.0, 0.0]);
+        assert_vec_close(&a.up(), &q.up());
+        assert_vec_close(&a.forward(), &q.forward());
+    }
+
+    #[test]
+    fn rotated_field_matches_rotated_source() {
+        let o = Orientation::from_yaw_pitch_roll(0.4, -0.7, 1.1);
+        let rotation = BformatRotation::from_orientation(&o);
+
+        let source = [0.2, 0.9, -0.3];
+        let rotated = rotation.apply(
+            Bweights::from_direction(source).scale(1.0),
+            AmbisonicOrder::Third,
+        );
+        let expected = Bweights::from_direction(o.to_listener(source)).scale(1.0);
+
+        assert_vec_close(rotated.components(), expected.components());
+    }
+
+    #[test]
+    fn turns_keep_the_level_while_crossfading() {
+        let sample_rate = 1000;
+        let order = AmbisonicOrder::Third;
+        let energy = |b: Bformat| b.components().iter().map(|x| x * x).sum::<f32>();
+
+        for yaw in [0.05, FRAC_PI_2, std::f32::consts::PI] {
+            let o = Orientation::from_yaw_pitch_roll(yaw, 0.0, 0.0);
+            let mut rotation = SmoothRotation::new(sample_rate);
+            rotation.set_target(BformatRotation::from_orientation(&o), false);
+
+            let source = [0.3, 0.8, 0.1];
+            let b = Bweights::from_direction(source).scale(1.0);
+            let expected = energy(b);
+            for _ in 0..2 * rotation.ramp_length {
+                let e = energy(rotation.process(b, order));
+                assert!(
+                    (e / expected - 1.0).abs() < 0.2,
+                    "energy {} instead of {} at yaw {}",
+                    e,
+                    expected,
+                    yaw
+                );
+            }
+
+            let rotated = rotation.process(b, order);
+            let expected = Bweights::from_direction(o.to_listener(source)).scale(1.0);
+            assert_vec_close(rotated.components(), expected.components());
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Load head related transfer functions from SOFA files
+//!
+//! SOFA (Spatially Oriented Format for Acoustics) is the standard file format for HRTF
+//! databases. Only files following the *SimpleFreeFieldHRIR* convention are supported.
+
+use std::path::Path;
+
+use sofar::reader::{Filter, OpenOptions, Sofar};
+
+use crate::bformat::AmbisonicOrder;
+use crate::decoder::{self, DecoderMethod};
+use crate::error::Error;
+use crate::renderer::{HrtfConfig, VirtualSpeaker};
+use crate::sphere;
+
+impl HrtfConfig {
+    /// Load HRTFs from a SOFA file.
+    ///
+    /// A set of virtual speakers suitable for decoding the given ambisonic `order` is placed
+    /// around the listener. The HRIR of each virtual speaker is interpolated from the
+    /// measurements nearest to its direction and resampled to `sample_rate`.
+    pub fn from_sofa<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        order: AmbisonicOrder,
+    ) -> Result<Self, Error> {
+        let sofa = open_options(sample_rate).open(path)?;
+        Ok(Self::from_sofar(&sofa, sample_rate, order, 1.0))
+    }
+
+    /// Load HRTFs measured at a given distance in metres from a SOFA file.
+    ///
+    /// The virtual speakers use the measurements nearest to their direction at `distance`. With
+    /// a file that contains near-field measurements, close sources reproduce the level
+    /// differences between the ears of real close sounds when their near field is compensated
+    /// with the same radius (see `BstreamConfig::with_near_field`).
+    pub fn from_sofa_at_distance<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        order: AmbisonicOrder,
+        distance: f32,
+    ) -> Result<Self, Error> {
+        let sofa = open_options(sample_rate).open(path)?;
+        Ok(Self::from_sofar(&sofa, sample_rate, order, distance))
+    }
+
+    /// Load HRTFs from SOFA data in memory.
+    ///
+    /// See `from_sofa` for details.
+    pub fn from_sofa_data(
+        data: &[u8],
+        sample_rate: u32,
+        order: AmbisonicOrder,
+    ) -> Result<Self, Error> {
+        let sofa = open_options(sample_rate).open_data(data)?;
+        Ok(Self::from_sofar(&sofa, sample_rate, order, 1.0))
+    }
+
+    fn from_sofar(sofa: &Sofar, sample_rate: u32, order: AmbisonicOrder, distance: f32) -> Self {
+        let directions = sphere::design_for_order(order);
+        let decoder = decoder::design(&directions, order, DecoderMethod::ModeMatching);
+
+        let mut filter = Filter::new(sofa.filter_len());
+
+        let virtual_speakers = directions
+            .iter()
+            .zip(decoder)
+            .map(|(dir, bweights)| {
+                // SOFA coordinates: x to the front, y to the left, z up
+                let [x, y, z] = dir.map(|c| c * distance);
+                sofa.filter(y, -x, z, &mut filter);
+                VirtualSpeaker {
+                    bweights,
+                    left_hrir: delayed(&filter.left, filter.ldelay, sample_rate),
+                    right_hrir: delayed(&filter.right, filter.rdelay, sample_rate),
+                }
+            })
+            .collect();
+
+        HrtfConfig {
+            sample_rate,
+            virtual_speakers,
+        }
+    }
+}
+
+fn open_options(sample_rate: u32) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.sample_rate(sample_rate as f32);
+    options
+}
+
+/// Prepend the filter's delay to the impulse response
+fn delayed(hrir: &[f32], delay: f32, sample_rate: u32) -> Vec<f32> {
+    let n = (delay * sample_rate as f32).round().max(0.0) as usize;
+    let mut output = vec![0.0; n];
+    output.extend_from_slice(hrir);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files_are_reported() {
+        let result = HrtfConfig::from_sofa("does/not/exist.sofa", 48000, AmbisonicOrder::First);
+        assert!(matches!(result, Err(Error::Sofa(_))));
+    }
+
+    #[test]
+    fn hrirs_are_selected_by_direction_and_distance() {
+        // measurements in the four first-order speaker directions at 1 m (0 to 3) and at 2 m
+        // (4 to 7); the HRIRs of measurement `m` are impulses at tap `m`. The file is the
+        // netCDF4 test file of `sofar` with its positions and impulse responses replaced.
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/tetrahedron_near_far.sofa"
+        );
+        let peak = |hrir: &[f32]| {
+            (0..hrir.len())
+                .max_by(|&i, &j| hrir[i].abs().total_cmp(&hrir[j].abs()))
+                .unwrap()
+        };
+
+        for &(distance, first) in &[(1.0, 0), (1.2, 0), (1.8, 4), (2.0, 4)] {
+            let config =
+                HrtfConfig::from_sofa_at_distance(path, 48000, AmbisonicOrder::First, distance)
+                    .unwrap();
+
+            assert_eq!(config.virtual_speakers.len(), 4);
+            for (i, speaker) in config.virtual_speakers.iter().enumerate() {
+                assert_eq!(peak(&speaker.left_hrir), first + i);
+                assert_eq!(peak(&speaker.right_hrir), first + i);
+            }
+        }
+    }
+}
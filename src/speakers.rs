@@ -0,0 +1,309 @@
+//! Playback over arbitrary loudspeaker layouts
+
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::bformat::{AmbisonicOrder, Bformat, BformatFrames, Bweights};
+use crate::decoder::{self, DecoderMethod};
+use crate::renderer::Renderer;
+use crate::sphere;
+
+/// Loudspeaker playback configuration
+///
+/// Describes the direction of each speaker relative to the listener, in the order of the output
+/// channels. Presets for common layouts use the channel order of WAV files (e.g. *L R C LFE Ls Rs*
+/// for 5.1). The low frequency effects channel is not part of the decoded sound field and
+/// remains silent.
+///
+/// By default, the decoder is designed for the ambisonic order of the scene, using AllRAD.
+#[derive(Debug, Clone)]
+pub struct SpeakerConfig {
+    speakers: Vec<Option<[f32; 3]>>,
+    pub(crate) order: Option<AmbisonicOrder>,
+    method: DecoderMethod,
+}
+
+impl SpeakerConfig {
+    /// Construct a layout from speaker directions.
+    ///
+    /// Directions are given in the same coordinates as sound sources: `x` to the right, `y` to
+    /// the front, and `z` up. They do not need to be normalized.
+    pub fn new(directions: Vec<[f32; 3]>) -> Self {
+        SpeakerConfig {
+            speakers: directions
+                .into_iter()
+                .map(|d| Some(sphere::normalize(d)))
+                .collect(),
+            order: None,
+            method: DecoderMethod::default(),
+        }
+    }
+
+    /// Construct a layout from speaker angles `(azimuth, elevation)` in degrees.
+    ///
+    /// Positive azimuths are to the left of the listener, positive elevations above.
+    pub fn from_angles(angles: &[(f32, f32)]) -> Self {
+        SpeakerConfig::new(angles.iter().map(|&(a, e)| direction(a, e)).collect())
+    }
+
+    /// Four speakers at +/- 45º and +/- 135º (*FL FR BL BR*)
+    pub fn quad() -> Self {
+        SpeakerConfig::from_angles(&[(45.0, 0.0), (-45.0, 0.0), (135.0, 0.0), (-135.0, 0.0)])
+    }
+
+    /// 5.1 surround sound (*L R C LFE Ls Rs*)
+    pub fn surround_5_1() -> Self {
+        SpeakerConfig::from_angles(&[
+            (30.0, 0.0),
+            (-30.0, 0.0),
+            (0.0, 0.0),
+            (110.0, 0.0),
+            (-110.0, 0.0),
+        ])
+        .with_lfe(3)
+    }
+
+    /// 7.1 surround sound (*L R C LFE Lb Rb Ls Rs*)
+    pub fn surround_7_1() -> Self {
+        SpeakerConfig::from_angles(&SURROUND_7_1).with_lfe(3)
+    }
+
+    /// 7.1.4 surround sound: 7.1 and four height speakers (*Ltf Rtf Ltb Rtb*) at 45º elevation
+    pub fn surround_7_1_4() -> Self {
+        let mut angles = SURROUND_7_1.to_vec();
+        angles.extend([(45.0, 45.0), (-45.0, 45.0), (135.0, 45.0), (-135.0, 45.0)]);
+        SpeakerConfig::from_angles(&angles).with_lfe(3)
+    }
+
+    /// `n` speakers evenly spaced on a horizontal ring.
+    ///
+    /// The first speaker is in front of the listener, and the following speakers proceed
+    /// counter-clockwise (to the left).
+    pub fn ring(n: usize) -> Self {
+        SpeakerConfig::dome(&[(n, 0.0)])
+    }
+
+    /// A dome of horizontal rings, given as `(speaker count, elevation in degrees)`.
+    ///
+    /// For example, `SpeakerConfig::dome(&[(8, 0.0), (4, 45.0), (1, 90.0)])` describes eight
+    /// speakers at ear level, four above, and one at the zenith. Each ring starts in front of
+    /// the listener and proceeds counter-clockwise.
+    pub fn dome(rings: &[(usize, f32)]) -> Self {
+        let angles: Vec<_> = rings
+            .iter()
+            .flat_map(|&(n, elevation)| {
+                (0..n).map(move |i| (360.0 * i as f32 / n as f32, elevation))
+            })
+            .collect();
+        SpeakerConfig::from_angles(&angles)
+    }
+
+    /// Insert a silent low frequency effects channel at position `channel`.
+    pub fn with_lfe(mut self, channel: usize) -> Self {
+        self.speakers.insert(channel, None);
+        self
+    }
+
+    /// Design the decoder for a fixed ambisonic order, instead of the order of the scene.
+    pub fn with_order(mut self, order: AmbisonicOrder) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /// Set the method that computes the decoding matrix.
+    pub fn with_method(mut self, method: DecoderMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Number of output channels
+    pub fn channels(&self) -> usize {
+        self.speakers.len()
+    }
+
+    /// Compute decoder weights for every output channel
+    fn decoder(&self, order: AmbisonicOrder) -> Vec<Bweights> {
+        let directions: Vec<_> = self.speakers.iter().flatten().cloned().collect();
+        let mut weights = decoder::design(&directions, order, self.method).into_iter();
+        self.speakers
+            .iter()
+            .map(|speaker| match speaker {
+                Some(_) => weights.next().expect("one set of weights per speaker"),
+                None => Bweights::new(0.0, 0.0, 0.0, 0.0),
+            })
+            .collect()
+    }
+}
+
+const SURROUND_7_1: [(f32, f32); 7] = [
+    (30.0, 0.0),
+    (-30.0, 0.0),
+    (0.0, 0.0),
+    (135.0, 0.0),
+    (-135.0, 0.0),
+    (90.0, 0.0),
+    (-90.0, 0.0),
+];
+
+/// Convert azimuth and elevation in degrees to a direction vector
+fn direction(azimuth: f32, elevation: f32) -> [f32; 3] {
+    let (a, e) = (azimuth.to_radians(), elevation.to_radians());
+    [-a.sin() * e.cos(), a.cos() * e.cos(), e.sin()]
+}
+
+/// Render a *B-format* stream to a loudspeaker layout.
+///
+/// Produces one channel per speaker.
+pub struct BstreamSpeakerRenderer<I> {
+    input: BformatFrames<I>,
+    decoder: Vec<Bweights>,
+    frame: Vec<f32>,
+    position: usize,
+}
+
+impl<I> BstreamSpeakerRenderer<I> {
+    /// Construct a new loudspeaker renderer for an input of the scene's ambisonic order
+    ///
+    /// The decoder is designed for `order`, unless the configuration specifies an order.
+    pub fn new(input: I, config: SpeakerConfig, order: AmbisonicOrder) -> Self {
+        let decoder = config.decoder(config.order.unwrap_or(order));
+        BstreamSpeakerRenderer {
+            input: BformatFrames::new(input),
+            frame: vec![0.0; decoder.len()],
+            position: decoder.len(),
+            decoder,
+        }
+    }
+}
+
+impl<I> Source for BstreamSpeakerRenderer<I>
+where
+    I: Source<Item = f32>,
+{
+    #[inline(always)]
+    fn current_frame_len(&self) -> Option<usize> {
+        let channels = Source::channels(self) as usize;
+        self.input.current_frame_count().map(|n| n * channels)
+    }
+
+    #[inline(always)]
+    fn channels(&self) -> u16 {
+        self.decoder.len() as u16
+    }
+
+    #[inline(always)]
+    fn sample_rate(&self) -> u32 {
+        self.input.inner().sample_rate()
+    }
+
+    #[inline(always)]
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.inner().total_duration()
+    }
+}
+
+impl<I> Renderer for BstreamSpeakerRenderer<I> {
+    fn channels(&self) -> u16 {
+        self.decoder.len() as u16
+    }
+
+    fn render(&mut self, input: &Bformat, output: &mut [f32]) {
+        for (out, weights) in output.iter_mut().zip(&self.decoder) {
+            *out = weights.dot(*input);
+        }
+    }
+}
+
+impl<I> Iterator for BstreamSpeakerRenderer<I>
+where
+    I: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.frame.len() {
+            let sample = self.input.next()?;
+            let mut frame = std::mem::take(&mut self.frame);
+            self.render(&sample, &mut frame);
+            self.frame = frame;
+            self.position = 0;
+        }
+
+        let output = self.frame[self.position];
+        self.position += 1;
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_have_expected_channel_counts() {
+        assert_eq!(SpeakerConfig::quad().channels(), 4);
+        assert_eq!(SpeakerConfig::surround_5_1().channels(), 6);
+        assert_eq!(SpeakerConfig::surround_7_1().channels(), 8);
+        assert_eq!(SpeakerConfig::surround_7_1_4().channels(), 12);
+        assert_eq!(SpeakerConfig::ring(16).channels(), 16);
+        assert_eq!(
+            SpeakerConfig::dome(&[(8, 0.0), (4, 45.0), (1, 90.0)]).channels(),
+            13
+        );
+    }
+
+    #[test]
+    fn lfe_channel_is_silent() {
+        let decoder = SpeakerConfig::surround_5_1().decoder(AmbisonicOrder::Third);
+        assert!(decoder[3].components().iter().all(|&w| w == 0.0));
+    }
+
+    #[test]
+    fn sources_at_speakers_are_loudest_there() {
+        for config in &[
+            SpeakerConfig::quad(),
+            SpeakerConfig::surround_5_1(),
+            SpeakerConfig::surround_7_1(),
+            SpeakerConfig::surround_7_1_4(),
+            SpeakerConfig::dome(&[(8, 0.0), (4, 45.0), (1, 90.0)]),
+        ] {
+            let decoder = config.decoder(AmbisonicOrder::Third);
+            for (i, speaker) in config.speakers.iter().enumerate() {
+                if let Some(dir) = speaker {
+                    let source = Bweights::from_direction(*dir).scale(1.0);
+                    let signals: Vec<f32> = decoder.iter().map(|w| w.dot(source)).collect();
+                    let loudest = (0..signals.len())
+                        .max_by(|&a, &b| signals[a].total_cmp(&signals[b]))
+                        .unwrap();
+                    assert_eq!(loudest, i, "{:?}", config.speakers);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decoder_defaults_to_the_scene_order() {
+        let input = rodio::buffer::SamplesBuffer::new(16, 48000, vec![0.0; 16]);
+        let renderer =
+            BstreamSpeakerRenderer::new(input, SpeakerConfig::ring(16), AmbisonicOrder::Third);
+        assert!(renderer.decoder[0].components()[9..]
+            .iter()
+            .any(|&w| w != 0.0));
+
+        let input = rodio::buffer::SamplesBuffer::new(16, 48000, vec![0.0; 16]);
+        let config = SpeakerConfig::ring(16).with_order(AmbisonicOrder::First);
+        let renderer = BstreamSpeakerRenderer::new(input, config, AmbisonicOrder::Third);
+        assert!(renderer.decoder[0].components()[4..]
+            .iter()
+            .all(|&w| w == 0.0));
+    }
+
+    #[test]
+    fn angles_follow_source_coordinates() {
+        let left = direction(90.0, 0.0);
+        assert!((left[0] + 1.0).abs() < 1e-6 && left[1].abs() < 1e-6);
+        let up = direction(0.0, 90.0);
+        assert!((up[2] - 1.0).abs() < 1e-6);
+    }
+}
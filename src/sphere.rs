@@ -0,0 +1,74 @@
+//! Point sets on the unit sphere
+
+use crate::bformat::{norm, AmbisonicOrder};
+
+/// Unit vectors that cover the sphere evenly enough to represent a sound field of given order
+///
+/// The sets are the vertices of regular polyhedra: a tetrahedron for first order, an
+/// icosahedron for second order, and icosahedron and dodecahedron combined for third order.
+pub fn design_for_order(order: AmbisonicOrder) -> Vec<[f32; 3]> {
+    let directions = match order {
+        AmbisonicOrder::First => tetrahedron(),
+        AmbisonicOrder::Second => icosahedron(),
+        AmbisonicOrder::Third => icosahedron().into_iter().chain(dodecahedron()).collect(),
+    };
+
+    directions.into_iter().map(normalize).collect()
+}
+
+/// Normalize a vector to unit length
+pub fn normalize(d: [f32; 3]) -> [f32; 3] {
+    let l = norm(d);
+    [d[0] / l, d[1] / l, d[2] / l]
+}
+
+/// Approximately uniform distribution of `n` points on the sphere (Fibonacci lattice)
+pub fn fibonacci(n: usize) -> Vec<[f32; 3]> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+    (0..n)
+        .map(|i| {
+            let z = 1.0 - (2 * i + 1) as f32 / n as f32;
+            let r = (1.0 - z * z).sqrt();
+            let phi = golden_angle * i as f32;
+            [r * phi.cos(), r * phi.sin(), z]
+        })
+        .collect()
+}
+
+fn tetrahedron() -> Vec<[f32; 3]> {
+    vec![
+        [-(2.0f32 / 3.0).sqrt(), (2.0f32 / 9.0).sqrt(), -1.0 / 3.0],
+        [(2.0f32 / 3.0).sqrt(), (2.0f32 / 9.0).sqrt(), -1.0 / 3.0],
+        [0.0, -(8.0f32 / 9.0).sqrt(), -1.0 / 3.0],
+        [0.0, 0.0, 1.0],
+    ]
+}
+
+fn icosahedron() -> Vec<[f32; 3]> {
+    let phi = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut points = vec![];
+    for &a in &[-1.0, 1.0] {
+        for &b in &[-phi, phi] {
+            points.push([0.0, a, b]);
+            points.push([a, b, 0.0]);
+            points.push([b, 0.0, a]);
+        }
+    }
+    points
+}
+
+fn dodecahedron() -> Vec<[f32; 3]> {
+    let phi = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut points = vec![];
+    for &a in &[-1.0, 1.0] {
+        for &b in &[-1.0, 1.0] {
+            for &c in &[-1.0, 1.0] {
+                points.push([a, b, c]);
+            }
+            points.push([0.0, a / phi, b * phi]);
+            points.push([a / phi, b * phi, 0.0]);
+            points.push([b * phi, 0.0, a / phi]);
+        }
+    }
+    points
+}
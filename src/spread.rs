@@ -0,0 +1,135 @@
+//! Spatial extent of sound sources
+
+use crate::bformat::{Bweights, COMPONENT_DEGREES, MAX_ORDER};
+
+/// Spatial extent of a sound source.
+///
+/// Point sources are encoded in a single direction. Extended sources, such as waterfalls or
+/// rain, cover a range of directions around the source's position; their directional components
+/// are reduced as if the sound came evenly from a circular patch of the sphere around the
+/// listener. A source that surrounds the listener completely is heard from all directions.
+///
+/// The default is a point source.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Spread {
+    extent: Extent,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Extent {
+    Point,
+    Angle(f32),
+    Sphere(f32),
+}
+
+impl Spread {
+    /// Sources are points.
+    pub fn point() -> Self {
+        Spread {
+            extent: Extent::Point,
+        }
+    }
+
+    /// Sources cover a fixed angular width in degrees, from 0 (a point) to 360 (all
+    /// directions).
+    pub fn angle(width: f32) -> Self {
+        Spread {
+            extent: Extent::Angle(width.to_radians().clamp(0.0, 2.0 * std::f32::consts::PI) / 2.0),
+        }
+    }
+
+    /// Sources are spheres of given radius.
+    ///
+    /// The angular width depends on the source's distance: near sources appear wider, and the
+    /// listener is surrounded by the source inside the sphere.
+    pub fn sphere(radius: f32) -> Self {
+        Spread {
+            extent: Extent::Sphere(radius.max(0.0)),
+        }
+    }
+
+    /// Half of the angle that the source covers at given distance, in radians
+    fn half_angle(&self, distance: f32) -> f32 {
+        match self.extent {
+            Extent::Point => 0.0,
+            Extent::Angle(half_angle) => half_angle,
+            Extent::Sphere(radius) if radius >= distance => std::f32::consts::PI,
+            Extent::Sphere(radius) => (radius / distance).asin(),
+        }
+    }
+
+    /// Reduce the directional components of `weights` for a source at given distance
+    pub(crate) fn apply(&self, weights: &mut Bweights, distance: f32) {
+        let gains = order_gains(self.half_angle(distance));
+        for (w, &degree) in weights.components_mut().iter_mut().zip(&COMPONENT_DEGREES) {
+            *w *= gains[degree];
+        }
+    }
+}
+
+impl Default for Spread {
+    fn default() -> Self {
+        Self::point()
+    }
+}
+
+/// Gain of each order for sound that comes evenly from a spherical cap of given half angle
+///
+/// The gains are the spherical harmonic coefficients of the cap, relative to those of a point:
+/// `(P(n-1) - P(n+1)) / ((2n + 1) (1 - cos(angle)))`, where `P(n)` is the Legendre polynomial of
+/// degree `n`, evaluated at `cos(angle)`.
+fn order_gains(half_angle: f32) -> [f32; MAX_ORDER + 1] {
+    let x = half_angle.cos();
+    let mut gains = [1.0; MAX_ORDER + 1];
+    if 1.0 - x < 1e-6 {
+        return gains;
+    }
+
+    let mut legendre = [0.0; MAX_ORDER + 2];
+    legendre[0] = 1.0;
+    legendre[1] = x;
+    for n in 1..=MAX_ORDER {
+        legendre[n + 1] =
+            ((2 * n + 1) as f32 * x * legendre[n] - n as f32 * legendre[n - 1]) / (n + 1) as f32;
+    }
+
+    for (n, gain) in gains.iter_mut().enumerate().skip(1) {
+        *gain = (legendre[n - 1] - legendre[n + 1]) / ((2 * n + 1) as f32 * (1.0 - x));
+    }
+    gains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spread_reduces_higher_orders_more() {
+        let gains = order_gains(std::f32::consts::FRAC_PI_4);
+        assert_eq!(gains[0], 1.0);
+        assert!(gains.windows(2).all(|g| g[1] < g[0]));
+
+        // the first-order gain of a cap is the mean cosine over the cap: (1 + cos(angle)) / 2
+        let expected = (1.0 + std::f32::consts::FRAC_1_SQRT_2) / 2.0;
+        assert!((gains[1] - expected).abs() < 1e-5);
+
+        assert!(order_gains(std::f32::consts::PI)[1..]
+            .iter()
+            .all(|g| g.abs() < 1e-6));
+    }
+
+    #[test]
+    fn listeners_inside_spheres_hear_them_from_all_directions() {
+        let mut weights = Bweights::from_direction([1.0, 0.0, 0.0]);
+        Spread::sphere(5.0).apply(&mut weights, 2.0);
+        assert_eq!(
+            weights.components()[0],
+            Bweights::omni_source().components()[0]
+        );
+        assert!(weights.components()[1..].iter().all(|w| w.abs() < 1e-6));
+
+        let mut weights = Bweights::from_direction([1.0, 0.0, 0.0]);
+        Spread::sphere(1.0).apply(&mut weights, 1000.0);
+        assert!((weights.components()[1] - 1.0).abs() < 1e-3);
+    }
+}